@@ -0,0 +1,84 @@
+// Range queries against the enemy grid versus scanning every enemy, the way `spawn_bullet` used to.
+// Run with `cargo bench --bench spatial`.
+
+#[path = "../src/spatial.rs"]
+mod spatial;
+
+use bevy::prelude::*;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use spatial::SpatialGrid;
+use std::hint::black_box;
+
+const ENEMY_COUNTS: [usize; 5] = [1_000, 2_000, 5_000, 10_000, 20_000];
+const TOWERS: usize = 50;
+// the ground plane runs from -10 to 10 and the base attack radius is about half a unit
+const BOUNDS: f32 = 10.0;
+const RANGE: f32 = 0.5;
+const CELL_SIZE: f32 = 0.5;
+
+fn positions(rng: &mut ChaCha8Rng, count: usize, y: f32) -> Vec<Vec3> {
+    (0..count)
+        .map(|_| {
+            Vec3::new(
+                rng.gen_range(-BOUNDS..BOUNDS),
+                y,
+                rng.gen_range(-BOUNDS..BOUNDS),
+            )
+        })
+        .collect()
+}
+
+fn range_queries(c: &mut Criterion) {
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    let towers = positions(&mut rng, TOWERS, 0.3);
+    let mut group = c.benchmark_group("enemies in range of every tower");
+    for count in ENEMY_COUNTS {
+        let enemies: Vec<(Entity, Vec3)> = positions(&mut rng, count, 0.1)
+            .into_iter()
+            .enumerate()
+            .map(|(i, position)| (Entity::from_raw(i as u32), position))
+            .collect();
+
+        group.bench_with_input(
+            BenchmarkId::new("brute force", count),
+            &enemies,
+            |b, enemies| {
+                b.iter(|| {
+                    let mut hits = 0;
+                    for tower in towers.iter() {
+                        hits += enemies
+                            .iter()
+                            .filter(|(_, position)| tower.distance(*position) <= RANGE)
+                            .count();
+                    }
+                    black_box(hits)
+                })
+            },
+        );
+
+        // includes the once per frame rebuild, which is what the game pays for
+        let mut grid = SpatialGrid::new(CELL_SIZE);
+        group.bench_with_input(BenchmarkId::new("grid", count), &enemies, |b, enemies| {
+            b.iter(|| {
+                grid.clear();
+                for (enemy, position) in enemies.iter() {
+                    grid.insert(*enemy, *position);
+                }
+                let mut hits = 0;
+                for tower in towers.iter() {
+                    hits += grid
+                        .within(*tower, RANGE)
+                        .filter(|(_, position)| tower.distance(*position) <= RANGE)
+                        .count();
+                }
+                black_box(hits)
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, range_queries);
+criterion_main!(benches);
@@ -0,0 +1,140 @@
+use crate::ron_asset::RonAssetPlugin;
+use bevy::audio::{PlaybackMode, Volume};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Plays the sound effects. Gameplay only sends `PlaySfx`, so a run without a window
+// just doesn't add this plugin.
+pub struct AudioManagerPlugin;
+
+impl Plugin for AudioManagerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(RonAssetPlugin::<AudioConfig>::new(
+            "default.audio.ron",
+            &["audio.ron"],
+            include_str!("../assets/default.audio.ron"),
+        ));
+        app.init_resource::<VolumeSettings>();
+        app.add_systems(Update, play_sfx);
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Sfx {
+    Shoot,
+    EnemyDeath,
+}
+
+#[derive(Event, Clone, Copy)]
+pub struct PlaySfx(pub Sfx);
+
+#[derive(Asset, Resource, TypePath, Deserialize, Clone)]
+pub struct AudioConfig {
+    pub sounds: HashMap<Sfx, SoundDef>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SoundDef {
+    pub path: String,
+    pub volume: f32,
+    #[serde(default = "default_speed")]
+    pub speed: f32,
+    // each play is pitched up or down by up to this fraction
+    #[serde(default)]
+    pub pitch_jitter: f32,
+    // further plays are dropped while this many are still playing
+    pub max_voices: usize,
+    // or while the last one started less than this long ago
+    #[serde(default)]
+    pub cooldown_secs: f32,
+}
+
+fn default_speed() -> f32 {
+    1.0
+}
+
+// Every sound is scaled by `master` and then by its bus.
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(default)]
+pub struct VolumeSettings {
+    pub master: f32,
+    pub sfx: f32,
+}
+
+impl Default for VolumeSettings {
+    fn default() -> Self {
+        Self {
+            master: 1.0,
+            sfx: 1.0,
+        }
+    }
+}
+
+#[derive(Component)]
+struct SfxVoice(Sfx);
+
+fn play_sfx(
+    mut commands: Commands,
+    mut events: EventReader<PlaySfx>,
+    config: Res<AudioConfig>,
+    settings: Res<VolumeSettings>,
+    asset_server: Res<AssetServer>,
+    time: Res<Time>,
+    voices: Query<&SfxVoice>,
+    mut last_played: Local<HashMap<Sfx, f32>>,
+) {
+    if events.is_empty() {
+        return;
+    }
+    let mut playing: HashMap<Sfx, usize> = HashMap::new();
+    for voice in voices.iter() {
+        *playing.entry(voice.0).or_default() += 1;
+    }
+    let now = time.elapsed_seconds();
+    for PlaySfx(sfx) in events.read() {
+        let Some(sound) = config.sounds.get(sfx) else {
+            continue;
+        };
+        let voices = playing.entry(*sfx).or_default();
+        if *voices >= sound.max_voices
+            || last_played
+                .get(sfx)
+                .is_some_and(|last| now - last < sound.cooldown_secs)
+        {
+            continue;
+        }
+        *voices += 1;
+        last_played.insert(*sfx, now);
+        // not the game rng, sounds must not change how a seeded run plays out
+        let pitch = 1.0 + sound.pitch_jitter * (rand::random::<f32>() * 2.0 - 1.0);
+        commands.spawn((
+            AudioBundle {
+                source: asset_server.load(&sound.path),
+                settings: PlaybackSettings {
+                    mode: PlaybackMode::Despawn,
+                    volume: Volume::new_relative(sound.volume * settings.sfx * settings.master),
+                    speed: sound.speed * pitch,
+                    paused: false,
+                    spatial: false,
+                },
+            },
+            SfxVoice(*sfx),
+        ));
+    }
+}
+
+// Takes the `ResMut` so that only moving a slider marks the settings as changed.
+pub fn volume_ui(ui: &mut egui::Ui, settings: &mut ResMut<VolumeSettings>) {
+    let mut edited = **settings;
+    egui::Grid::new("volume").show(ui, |ui| {
+        for (label, value) in [("master", &mut edited.master), ("effects", &mut edited.sfx)] {
+            ui.label(label);
+            ui.add(egui::Slider::new(value, 0.0..=1.0));
+            ui.end_row();
+        }
+    });
+    if edited != **settings {
+        **settings = edited;
+    }
+}
@@ -0,0 +1,220 @@
+use crate::ron_asset::RonAssetPlugin;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+pub struct DifficultyPlugin;
+
+impl Plugin for DifficultyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(RonAssetPlugin::<DifficultyPresets>::new(
+            "default.difficulties.ron",
+            &["difficulties.ron"],
+            include_str!("../assets/default.difficulties.ron"),
+        ));
+        app.init_resource::<Difficulty>();
+        app.init_resource::<Mutators>();
+    }
+}
+
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+    Nightmare,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 4] = [
+        Difficulty::Easy,
+        Difficulty::Normal,
+        Difficulty::Hard,
+        Difficulty::Nightmare,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Normal => "normal",
+            Difficulty::Hard => "hard",
+            Difficulty::Nightmare => "nightmare",
+        }
+    }
+}
+
+// Optional rules stacked on top of the difficulty, each paying out more gold.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug, PartialOrd, Ord)]
+pub enum Mutator {
+    DoubleSpeed,
+    NoUpgrades,
+    OneTower,
+    FragileCursor,
+}
+
+impl Mutator {
+    pub const ALL: [Mutator; 4] = [
+        Mutator::DoubleSpeed,
+        Mutator::NoUpgrades,
+        Mutator::OneTower,
+        Mutator::FragileCursor,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Mutator::DoubleSpeed => "double speed enemies",
+            Mutator::NoUpgrades => "no upgrades",
+            Mutator::OneTower => "one tower only",
+            Mutator::FragileCursor => "fragile cursor",
+        }
+    }
+}
+
+#[derive(Resource, Serialize, Deserialize, Default, Clone, PartialEq, Debug)]
+pub struct Mutators(pub BTreeSet<Mutator>);
+
+impl Mutators {
+    pub fn has(&self, mutator: Mutator) -> bool {
+        self.0.contains(&mutator)
+    }
+}
+
+#[derive(Asset, Resource, TypePath, Deserialize, Clone)]
+pub struct DifficultyPresets {
+    pub presets: HashMap<Difficulty, DifficultyPreset>,
+    // mutators missing here don't change the reward
+    pub mutator_gold_multipliers: HashMap<Mutator, f32>,
+}
+
+impl DifficultyPresets {
+    pub fn get(&self, difficulty: Difficulty) -> DifficultyPreset {
+        self.presets.get(&difficulty).cloned().unwrap_or_default()
+    }
+
+    // The difficulty's preset with the mutators applied on top.
+    pub fn modifiers(&self, difficulty: Difficulty, mutators: &Mutators) -> RunModifiers {
+        let preset = self.get(difficulty);
+        let mut modifiers = RunModifiers {
+            enemy_health: preset.enemy_health,
+            enemy_speed: preset.enemy_speed,
+            spawn_rate: preset.spawn_rate,
+            touch_radius: preset.touch_radius,
+            score_per_tower: preset.score_per_tower.max(1),
+            max_towers: None,
+            gold_multiplier: preset.gold_multiplier,
+        };
+        for mutator in mutators.0.iter() {
+            match mutator {
+                Mutator::DoubleSpeed => modifiers.enemy_speed *= 2.0,
+                Mutator::OneTower => modifiers.max_towers = Some(1),
+                // applied to the stats themselves by `Stats`
+                Mutator::NoUpgrades | Mutator::FragileCursor => {}
+            }
+            modifiers.gold_multiplier *= self
+                .mutator_gold_multipliers
+                .get(mutator)
+                .copied()
+                .unwrap_or(1.0);
+        }
+        modifiers
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct DifficultyPreset {
+    // multipliers on every enemy archetype's health and speed
+    pub enemy_health: f32,
+    pub enemy_speed: f32,
+    // multiplies how fast a wave's enemies come in
+    pub spawn_rate: f32,
+    // multiplies how close an enemy has to get to the cursor to hurt it
+    pub touch_radius: f32,
+    // one more tower is available for every this much score
+    pub score_per_tower: u32,
+    // multiplies the gold the run's score converts into
+    pub gold_multiplier: f32,
+    pub time_goal_minutes: f32,
+    // and this on top once the goal is reached
+    pub goal_gold_multiplier: f32,
+    pub overtime: OvertimeScaling,
+}
+
+impl Default for DifficultyPreset {
+    fn default() -> Self {
+        Self {
+            enemy_health: 1.0,
+            enemy_speed: 1.0,
+            spawn_rate: 1.0,
+            touch_radius: 1.0,
+            score_per_tower: 50,
+            gold_multiplier: 1.0,
+            time_goal_minutes: 6.666,
+            goal_gold_multiplier: 1.5,
+            overtime: OvertimeScaling {
+                health_per_minute: 0.5,
+                speed_per_minute: 0.1,
+            },
+        }
+    }
+}
+
+// Enemies spawned after the time goal get this much more health and speed for every minute
+// past it.
+#[derive(Deserialize, Clone)]
+pub struct OvertimeScaling {
+    pub health_per_minute: f32,
+    pub speed_per_minute: f32,
+}
+
+// Everything the chosen difficulty and mutators change about a run.
+#[derive(Clone, Copy, Debug)]
+pub struct RunModifiers {
+    pub enemy_health: f32,
+    pub enemy_speed: f32,
+    pub spawn_rate: f32,
+    pub touch_radius: f32,
+    pub score_per_tower: u32,
+    // caps the towers available over the whole run
+    pub max_towers: Option<u32>,
+    pub gold_multiplier: f32,
+}
+
+// Takes the `ResMut`s so that only an actual choice marks them as changed.
+pub fn difficulty_ui(
+    ui: &mut egui::Ui,
+    difficulty: &mut ResMut<Difficulty>,
+    mutators: &mut ResMut<Mutators>,
+    presets: &DifficultyPresets,
+) {
+    let mut chosen = **difficulty;
+    ui.horizontal(|ui| {
+        for option in Difficulty::ALL {
+            ui.selectable_value(&mut chosen, option, option.label());
+        }
+    });
+    if chosen != **difficulty {
+        **difficulty = chosen;
+    }
+    for mutator in Mutator::ALL {
+        let mut enabled = mutators.has(mutator);
+        let multiplier = presets
+            .mutator_gold_multipliers
+            .get(&mutator)
+            .copied()
+            .unwrap_or(1.0);
+        let label = format!("{} (gold x{})", mutator.label(), multiplier);
+        if ui.checkbox(&mut enabled, label).changed() {
+            if enabled {
+                mutators.0.insert(mutator);
+            } else {
+                mutators.0.remove(&mutator);
+            }
+        }
+    }
+    ui.label(format!(
+        "gold x{:.2}, time goal {} minutes",
+        presets.modifiers(chosen, mutators).gold_multiplier,
+        presets.get(chosen).time_goal_minutes
+    ));
+}
@@ -0,0 +1,379 @@
+use crate::main_game::game_over::GameOverPlugin;
+use crate::main_game::mouse::{MousePos, PlayerDamaged};
+use crate::main_game::pause::PausePlugin;
+use crate::main_game::replay::{live_input, LastReplay, Replay, ReplayMode};
+use crate::main_game::rng::FixedSeed;
+use crate::main_game::tower::{TimeSinceGameStart, TowerDestroyed};
+use crate::main_game::wave::{WaveCleared, WaveStarted};
+use crate::main_game::{MainGamePlugins, MainGameUiPlugin, RunEnded, TICK};
+use crate::{GamePlugin, GameState, GameStateChange};
+use bevy::app::{AppExit, ScheduleRunnerPlugin};
+use bevy::hierarchy::HierarchyPlugin;
+use bevy::input::mouse::MouseButtonInput;
+use bevy::input::{ButtonState, InputPlugin};
+use bevy::prelude::*;
+use bevy::scene::ScenePlugin;
+use bevy::time::TimeUpdateStrategy;
+use bevy::transform::TransformPlugin;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// batch runs treat any non-zero exit as a failure, so a mistyped flag can't pass as a run
+const BAD_ARGUMENT_EXIT_CODE: i32 = 2;
+
+#[derive(Resource, Clone)]
+pub struct HeadlessConfig {
+    pub seed: u64,
+    pub max_minutes: f32,
+    pub script: CursorScript,
+    // played back instead of the script, with its own seed
+    pub replay: Option<Replay>,
+    // where to write the run's replay
+    pub record: Option<PathBuf>,
+}
+
+impl Default for HeadlessConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            max_minutes: 10.0,
+            script: CursorScript::default(),
+            replay: None,
+            record: None,
+        }
+    }
+}
+
+// The scripted player circles the origin and tries to place a tower every `place_interval` seconds.
+#[derive(Clone)]
+pub struct CursorScript {
+    pub radius: f32,
+    pub angular_speed: f32,
+    pub place_interval: f32,
+}
+
+impl Default for CursorScript {
+    fn default() -> Self {
+        Self {
+            radius: 2.0,
+            angular_speed: 0.8,
+            place_interval: 5.0,
+        }
+    }
+}
+
+pub fn run() {
+    let mut config = HeadlessConfig::default();
+    let mut args = std::env::args()
+        .skip_while(|arg| arg != "--headless")
+        .skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_default();
+        let parsed = match arg.as_str() {
+            "--seed" => value.parse::<u64>().map(|seed| config.seed = seed).is_ok(),
+            "--minutes" => value.parse::<f32>().map(|m| config.max_minutes = m).is_ok(),
+            "--radius" => value
+                .parse::<f32>()
+                .map(|r| config.script.radius = r)
+                .is_ok(),
+            "--place-interval" => value
+                .parse::<f32>()
+                .map(|i| config.script.place_interval = i)
+                .is_ok(),
+            "--record" => {
+                config.record = Some(PathBuf::from(&value));
+                !value.is_empty()
+            }
+            "--replay" => match load_replay(&value) {
+                Ok(replay) => {
+                    config.replay = Some(replay);
+                    true
+                }
+                Err(err) => {
+                    eprintln!("could not load replay {value}: {err}");
+                    std::process::exit(BAD_ARGUMENT_EXIT_CODE);
+                }
+            },
+            _ => false,
+        };
+        if !parsed {
+            eprintln!("unknown or malformed headless argument {arg} {value}");
+            std::process::exit(BAD_ARGUMENT_EXIT_CODE);
+        }
+    }
+    build_app(config).run();
+}
+
+pub fn build_app(config: HeadlessConfig) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::ZERO)))
+        .add_plugins((
+            AssetPlugin::default(),
+            TransformPlugin,
+            HierarchyPlugin,
+            InputPlugin,
+            ScenePlugin,
+        ))
+        .init_asset::<Mesh>()
+        .init_asset::<StandardMaterial>()
+        .insert_resource(TimeUpdateStrategy::ManualDuration(TICK))
+        .add_plugins(GamePlugin)
+        .add_plugins(
+            MainGamePlugins
+                .build()
+                .disable::<MainGameUiPlugin>()
+                .disable::<PausePlugin>()
+                .disable::<GameOverPlugin>(),
+        )
+        .insert_resource(FixedSeed(Some(config.seed)))
+        .add_systems(Startup, start_run)
+        .add_systems(
+            Update,
+            (drive_cursor, end_run_after_time_limit)
+                .run_if(state_exists_and_equals(GameState::InGame).and_then(live_input)),
+        )
+        .init_resource::<Tally>()
+        .add_systems(Last, (tally, save_replay, report).chain());
+    if let Some(replay) = config.replay.clone() {
+        app.insert_resource(ReplayMode::play(replay));
+    }
+    app.insert_resource(config);
+    app
+}
+
+fn load_replay(path: &str) -> Result<Replay, String> {
+    let contents = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let replay: Replay = ron::from_str(&contents).map_err(|err| err.to_string())?;
+    replay.check_version()?;
+    Ok(replay)
+}
+
+fn start_run(mut event_writer: EventWriter<GameStateChange>) {
+    event_writer.send(GameStateChange::MainGame);
+}
+
+fn drive_cursor(
+    config: Res<HeadlessConfig>,
+    time_since_game_start: Res<TimeSinceGameStart>,
+    mut mouse_pos: ResMut<MousePos>,
+    mut mouse_button_events: EventWriter<MouseButtonInput>,
+    mut next_placement: Local<f32>,
+    mut held: Local<bool>,
+) {
+    let script = &config.script;
+    let t = time_since_game_start.0;
+    let angle = t * script.angular_speed;
+    mouse_pos.0 = Vec3::new(angle.cos(), 0.0, angle.sin()) * script.radius;
+    // release the click a frame later so the next one registers as a fresh press
+    if *held {
+        *held = false;
+        mouse_button_events.send(MouseButtonInput {
+            button: MouseButton::Left,
+            state: ButtonState::Released,
+            window: Entity::PLACEHOLDER,
+        });
+    }
+    if t < *next_placement {
+        return;
+    }
+    *next_placement = t + script.place_interval;
+    *held = true;
+    mouse_button_events.send(MouseButtonInput {
+        button: MouseButton::Left,
+        state: ButtonState::Pressed,
+        window: Entity::PLACEHOLDER,
+    });
+}
+
+fn end_run_after_time_limit(
+    config: Res<HeadlessConfig>,
+    time_since_game_start: Res<TimeSinceGameStart>,
+    mut event_writer: EventWriter<GameStateChange>,
+) {
+    if time_since_game_start.0 >= config.max_minutes * 60.0 {
+        event_writer.send(GameStateChange::Staging);
+    }
+}
+
+#[derive(Resource, Default)]
+struct Tally {
+    waves_reached: u32,
+    waves_cleared: u32,
+    towers_lost: u32,
+    hits_taken: u32,
+}
+
+fn tally(
+    mut tally: ResMut<Tally>,
+    mut wave_started: EventReader<WaveStarted>,
+    mut wave_cleared: EventReader<WaveCleared>,
+    mut tower_destroyed: EventReader<TowerDestroyed>,
+    mut player_damaged: EventReader<PlayerDamaged>,
+) {
+    for started in wave_started.read() {
+        tally.waves_reached = tally.waves_reached.max(started.0);
+    }
+    for cleared in wave_cleared.read() {
+        debug!("wave {} cleared", cleared.0);
+        tally.waves_cleared += 1;
+    }
+    for destroyed in tower_destroyed.read() {
+        debug!("tower {:?} destroyed", destroyed.0);
+        tally.towers_lost += 1;
+    }
+    for damaged in player_damaged.read() {
+        debug!(
+            "player hit for {}, {} left",
+            damaged.amount, damaged.remaining
+        );
+        tally.hits_taken += 1;
+    }
+}
+
+fn save_replay(config: Res<HeadlessConfig>, last_replay: Res<LastReplay>) {
+    if !last_replay.is_changed() {
+        return;
+    }
+    let (Some(path), Some(replay)) = (&config.record, &last_replay.0) else {
+        return;
+    };
+    match ron::to_string(replay) {
+        Ok(data) => {
+            if let Err(err) = std::fs::write(path, data) {
+                warn!("failed to write replay to {}: {err}", path.display());
+            }
+        }
+        Err(err) => warn!("failed to serialize replay: {err}"),
+    }
+}
+
+fn report(
+    mut run_ended: EventReader<RunEnded>,
+    tally: Res<Tally>,
+    mut app_exit: EventWriter<AppExit>,
+) {
+    if let Some(run) = run_ended.read().next() {
+        println!("score: {}", run.score);
+        println!("survival time: {:.1}s", run.survival_secs);
+        println!("towers placed: {}", run.towers_placed);
+        println!("towers lost: {}", tally.towers_lost);
+        println!("hits taken: {}", tally.hits_taken);
+        println!(
+            "waves reached: {}, cleared: {}",
+            tally.waves_reached, tally.waves_cleared
+        );
+        app_exit.send(AppExit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::main_game::game_over::RunSummary;
+
+    // ten minutes of ticks, far longer than any run here
+    const MAX_FRAMES: u32 = 36_000;
+
+    fn play_until_game_over(app: &mut App) {
+        for _ in 0..MAX_FRAMES {
+            app.update();
+            if *app.world.resource::<State<GameState>>().get() == GameState::GameOver {
+                return;
+            }
+        }
+        panic!("the run never ended");
+    }
+
+    // how many gameplay ticks the run lasted
+    fn survival_ticks(summary: &RunSummary) -> u32 {
+        (summary.survival_secs / TICK.as_secs_f32()).round() as u32
+    }
+
+    #[test]
+    fn replay_reproduces_recorded_run() {
+        let mut recording = build_app(HeadlessConfig {
+            seed: 7,
+            max_minutes: 0.5,
+            script: CursorScript {
+                place_interval: 2.0,
+                ..default()
+            },
+            ..default()
+        });
+        play_until_game_over(&mut recording);
+        let recorded = recording.world.resource::<RunSummary>();
+        let (score, towers_placed, survival_secs, ticks) = (
+            recorded.score,
+            recorded.towers_placed,
+            recorded.survival_secs,
+            survival_ticks(recorded),
+        );
+        let replay = recording
+            .world
+            .resource_mut::<LastReplay>()
+            .0
+            .take()
+            .expect("the run was recorded");
+        // something has to have happened for the comparison below to mean anything
+        assert!(score > 0);
+        assert!(towers_placed > 0);
+        assert_eq!(replay.ticks, ticks);
+
+        // a different seed, the replay brings its own
+        let mut playback = build_app(HeadlessConfig {
+            seed: 8,
+            replay: Some(replay),
+            ..default()
+        });
+        play_until_game_over(&mut playback);
+        let replayed = playback.world.resource::<RunSummary>();
+        assert_eq!(replayed.score, score);
+        assert_eq!(replayed.towers_placed, towers_placed);
+        assert_eq!(replayed.survival_secs, survival_secs);
+        assert_eq!(survival_ticks(replayed), ticks);
+    }
+
+    #[test]
+    fn replay_reproduces_run_in_same_app() {
+        let mut app = build_app(HeadlessConfig {
+            seed: 11,
+            max_minutes: 0.5,
+            script: CursorScript {
+                place_interval: 2.0,
+                ..default()
+            },
+            ..default()
+        });
+        play_until_game_over(&mut app);
+        let recorded = app.world.resource::<RunSummary>();
+        let (score, towers_placed, survival_secs, ticks) = (
+            recorded.score,
+            recorded.towers_placed,
+            recorded.survival_secs,
+            survival_ticks(recorded),
+        );
+        let replay = app
+            .world
+            .resource::<LastReplay>()
+            .0
+            .clone()
+            .expect("the run was recorded");
+        assert!(score > 0);
+        assert!(towers_placed > 0);
+        assert_eq!(replay.ticks, ticks);
+
+        // what the game over screen's continue button does, then "replay last run"
+        app.world
+            .resource_mut::<NextState<GameState>>()
+            .set(GameState::Staging);
+        app.update();
+        app.insert_resource(ReplayMode::play(replay));
+        app.world.send_event(GameStateChange::MainGame);
+        play_until_game_over(&mut app);
+        let replayed = app.world.resource::<RunSummary>();
+        assert_eq!(replayed.score, score);
+        assert_eq!(replayed.towers_placed, towers_placed);
+        assert_eq!(replayed.survival_secs, survival_secs);
+        assert_eq!(survival_ticks(replayed), ticks);
+    }
+}
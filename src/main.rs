@@ -1,6 +1,19 @@
+mod audio;
+mod difficulty;
+mod headless;
 mod main_game;
+mod ron_asset;
+mod save;
+mod spatial;
+mod upgrade;
 
-use crate::main_game::MainGamePlugins;
+use crate::audio::{volume_ui, AudioManagerPlugin, PlaySfx, VolumeSettings};
+use crate::difficulty::{difficulty_ui, Difficulty, DifficultyPlugin, DifficultyPresets, Mutators};
+use crate::main_game::input::{bindings_ui, capture_rebinding, Bindings, PlayerAction, Rebinding};
+use crate::main_game::replay::{LastReplay, ReplayMode};
+use crate::main_game::{GameLayer, MainGamePlugins, TICK};
+use crate::save::SavePlugin;
+use crate::upgrade::{Currency, UpgradeLevels, UpgradePlugin, UpgradeRegistry};
 use bevy::asset::AssetMetaCheck;
 use bevy::window::PrimaryWindow;
 use bevy::{prelude::*, render::camera::ScalingMode};
@@ -8,11 +21,16 @@ use bevy_egui::{EguiContexts, EguiPlugin};
 use bevy_mod_picking::debug::DebugPickingPlugin;
 use bevy_mod_picking::DefaultPickingPlugins;
 use bevy_xpbd_3d::plugins::PhysicsPlugins;
-use bevy_xpbd_3d::prelude::{Collider, RigidBody};
+use bevy_xpbd_3d::prelude::{Collider, CollisionLayers, PhysicsTimestep, RigidBody};
 use egui::CollapsingHeader;
-use leafwing_input_manager::systems::update_action_state;
+use leafwing_input_manager::prelude::ActionState;
+use serde::{Deserialize, Serialize};
 
 fn main() {
+    if std::env::args().any(|arg| arg == "--headless") {
+        headless::run();
+        return;
+    }
     App::new()
         .insert_resource(AssetMetaCheck::Never)
         .add_plugins(DefaultPlugins)
@@ -21,17 +39,36 @@ fn main() {
                 .build()
                 .disable::<DebugPickingPlugin>(),
         )
-        .add_plugins(PhysicsPlugins::default())
         .add_plugins(EguiPlugin)
+        .add_plugins(AudioManagerPlugin)
+        .add_plugins(GamePlugin)
         .add_plugins(MainGamePlugins)
-        .add_state::<GameState>()
-        .add_event::<GameStateChange>()
-        .add_systems(Startup, setup)
-        .add_systems(Update, change_game_state)
         .add_plugins(StagingPlugin)
+        .add_plugins(SavePlugin)
         .run();
 }
 
+// Everything a run needs whether or not there is a window to draw it in.
+pub struct GamePlugin;
+
+impl Plugin for GamePlugin {
+    fn build(&self, app: &mut App) {
+        // stepped once per gameplay tick
+        app.add_plugins(PhysicsPlugins::new(FixedUpdate))
+            .insert_resource(PhysicsTimestep::FixedOnce(TICK.as_secs_f32()))
+            .add_plugins(UpgradePlugin)
+            .add_plugins(DifficultyPlugin)
+            .add_state::<GameState>()
+            .add_event::<GameStateChange>()
+            .add_event::<PlaySfx>()
+            .add_systems(Startup, setup)
+            .add_systems(Update, change_game_state);
+        app.insert_resource(Gold(0.0));
+        app.insert_resource(Diamonds(0));
+        app.insert_resource(LifetimeStats::default());
+    }
+}
+
 #[cfg(target_family = "wasm")]
 fn update_canvas_size(mut window: Query<&mut Window, With<PrimaryWindow>>) {
     (|| {
@@ -50,13 +87,12 @@ impl Plugin for StagingPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(
             Update,
-            start_game_ui.run_if(state_exists_and_equals(GameState::Staging)),
+            (
+                start_game_ui,
+                start_game_on_action.before(capture_rebinding),
+            )
+                .run_if(state_exists_and_equals(GameState::Staging)),
         );
-        app.insert_resource(Gold(0.0));
-        app.insert_resource(UpgradeRadiusLvl(1));
-        app.insert_resource(DamageLvl(1));
-        app.insert_resource(AttackRadiusLvl(1));
-        app.insert_resource(GoldConversionRateLvl(2));
         #[cfg(target_family = "wasm")]
         app.add_systems(Update, update_canvas_size);
     }
@@ -64,10 +100,17 @@ impl Plugin for StagingPlugin {
 
 fn start_game_ui(
     mut gold: ResMut<Gold>,
-    mut upgrade_radius_lvl: ResMut<UpgradeRadiusLvl>,
-    mut attack_radius_lvl: ResMut<AttackRadiusLvl>,
-    mut damage_lvl: ResMut<DamageLvl>,
-    mut gold_conversion_rate_lvl: ResMut<GoldConversionRateLvl>,
+    mut diamonds: ResMut<Diamonds>,
+    registry: Res<UpgradeRegistry>,
+    mut levels: ResMut<UpgradeLevels>,
+    mut bindings: ResMut<Bindings>,
+    mut rebinding: ResMut<Rebinding>,
+    mut volume: ResMut<VolumeSettings>,
+    mut difficulty: ResMut<Difficulty>,
+    mut mutators: ResMut<Mutators>,
+    presets: Res<DifficultyPresets>,
+    last_replay: Res<LastReplay>,
+    mut replay_mode: ResMut<ReplayMode>,
     mut contexts: EguiContexts,
     mut event_writer: EventWriter<GameStateChange>,
 ) {
@@ -78,99 +121,111 @@ fn start_game_ui(
             if ui.button("start game").clicked() {
                 event_writer.send(GameStateChange::MainGame)
             }
+            // plays with the last run's seed and setup, and pays nothing out
+            if let Some(replay) = &last_replay.0 {
+                if ui.button("replay last run").clicked() {
+                    *replay_mode = ReplayMode::play(replay.clone());
+                    event_writer.send(GameStateChange::MainGame)
+                }
+            }
+            CollapsingHeader::new("difficulty")
+                .default_open(true)
+                .show(ui, |ui| {
+                    difficulty_ui(ui, &mut difficulty, &mut mutators, &presets);
+                });
             CollapsingHeader::new("upgrades")
                 .default_open(true)
                 .show(ui, |ui| {
                     ui.label(format!("gold {}", gold.0));
-                    if calculate_cost_to_upgrade(upgrade_radius_lvl.0) <= gold.0 as u32 {
-                        if ui
-                            .button(format!("upgrade radius level: {}", upgrade_radius_lvl.0))
-                            .clicked()
-                        {
-                            gold.0 -= calculate_cost_to_upgrade(upgrade_radius_lvl.0) as f32;
-                            upgrade_radius_lvl.0 += 1;
-                        }
-                    } else {
-                        ui.label(format!("upgrade radius level: {}", upgrade_radius_lvl.0));
-                        ui.label(format!(
-                            "needed to upgrade: {}",
-                            calculate_cost_to_upgrade(upgrade_radius_lvl.0)
-                        ));
-                    }
-                    if calculate_cost_to_upgrade(attack_radius_lvl.0) <= gold.0 as u32 {
-                        if ui
-                            .button(format!("attack radius level: {}", attack_radius_lvl.0))
-                            .clicked()
-                        {
-                            gold.0 -= calculate_cost_to_upgrade(attack_radius_lvl.0) as f32;
-                            attack_radius_lvl.0 += 1;
-                        }
-                    } else {
-                        ui.label(format!("attack radius level: {}", attack_radius_lvl.0));
-                        ui.label(format!(
-                            "needed to upgrade: {}",
-                            calculate_cost_to_upgrade(attack_radius_lvl.0)
-                        ));
-                    }
-                    if calculate_cost_to_upgrade(damage_lvl.0) <= gold.0 as u32 {
-                        if ui
-                            .button(format!("damage level: {}", damage_lvl.0))
-                            .clicked()
-                        {
-                            gold.0 -= calculate_cost_to_upgrade(damage_lvl.0) as f32;
-                            damage_lvl.0 += 1;
-                        }
-                    } else {
-                        ui.label(format!("damage level: {}", damage_lvl.0));
-                        ui.label(format!(
-                            "needed to upgrade: {}",
-                            calculate_cost_to_upgrade(damage_lvl.0)
-                        ));
+                    if let Some(cost) =
+                        upgrade_list(ui, &registry, &mut levels, Currency::Gold, gold.0 as u32)
+                    {
+                        gold.0 -= cost as f32;
                     }
-                    if calculate_cost_to_upgrade(gold_conversion_rate_lvl.0) <= gold.0 as u32 {
-                        if ui
-                            .button(format!(
-                                "gold conversion rate level: {}",
-                                gold_conversion_rate_lvl.0
-                            ))
-                            .clicked()
-                        {
-                            gold.0 -= calculate_cost_to_upgrade(gold_conversion_rate_lvl.0) as f32;
-                            gold_conversion_rate_lvl.0 += 1;
-                        }
-                    } else {
-                        ui.label(format!(
-                            "gold conversion rate level: {}",
-                            gold_conversion_rate_lvl.0
-                        ));
-                        ui.label(format!(
-                            "needed to upgrade: {}",
-                            calculate_cost_to_upgrade(gold_conversion_rate_lvl.0)
-                        ));
+                });
+            CollapsingHeader::new("permanent upgrades")
+                .default_open(true)
+                .show(ui, |ui| {
+                    ui.label(format!("diamonds {}", diamonds.0));
+                    if let Some(cost) =
+                        upgrade_list(ui, &registry, &mut levels, Currency::Diamonds, diamonds.0)
+                    {
+                        diamonds.0 -= cost;
                     }
                 });
             CollapsingHeader::new("stats")
                 .default_open(true)
                 .show(ui, |ui| {
-                    let attack_radius = attack_radius_lvl.0 as f32 / 15.0 + 1.0;
-                    let damage = damage_lvl.0 as f32 / 30.0 + 0.2;
-                    let gold_conversion_rate = ((gold_conversion_rate_lvl.0 as f32).log(1.5) / 5.0);
-                    let upgrade_radius = (upgrade_radius_lvl.0 as f32).log(1.1) / 25.0 + 0.5;
-                    ui.label(format!("attack radius: {}", attack_radius));
-                    ui.label(format!("bullet damage: {}", damage));
-                    ui.label(format!("gold conversion rate: {}", gold_conversion_rate));
-                    ui.label(format!("upgrade radius: {}", upgrade_radius));
+                    for stat in registry
+                        .stats(Currency::Gold)
+                        .into_iter()
+                        .chain(registry.stats(Currency::Diamonds))
+                    {
+                        ui.label(format!(
+                            "{}: {}",
+                            stat.label(),
+                            registry.stat(stat, &levels)
+                        ));
+                    }
                 });
+            CollapsingHeader::new("controls").show(ui, |ui| {
+                bindings_ui(ui, &mut bindings, &mut rebinding);
+            });
+            CollapsingHeader::new("volume").show(ui, |ui| {
+                volume_ui(ui, &mut volume);
+            });
         });
 }
 
+fn start_game_on_action(
+    action_state: Res<ActionState<PlayerAction>>,
+    rebinding: Res<Rebinding>,
+    mut event_writer: EventWriter<GameStateChange>,
+) {
+    // the press that finishes a rebind shouldn't also start the game
+    if rebinding.0.is_none() && action_state.just_pressed(PlayerAction::StartGame) {
+        event_writer.send(GameStateChange::MainGame);
+    }
+}
+
+// Lists the upgrades bought with `currency`, returning what was spent if one was bought.
+fn upgrade_list(
+    ui: &mut egui::Ui,
+    registry: &UpgradeRegistry,
+    levels: &mut UpgradeLevels,
+    currency: Currency,
+    funds: u32,
+) -> Option<u32> {
+    let mut spent = None;
+    for upgrade in registry.upgrades.iter().filter(|u| u.currency == currency) {
+        let level = levels.level(upgrade);
+        let label = format!("{} level: {}", upgrade.name, level);
+        if upgrade.max_level.is_some_and(|max| level >= max) {
+            ui.label(format!("{} (max)", label));
+            continue;
+        }
+        let cost = upgrade.cost.cost(level);
+        if cost <= funds {
+            if ui.button(label).clicked() {
+                spent = Some(cost);
+                levels.0.insert(upgrade.id.clone(), level + 1);
+            }
+        } else {
+            ui.label(label);
+            ui.label(format!("needed to upgrade: {}", cost));
+        }
+    }
+    spent
+}
+
 fn change_game_state(
     mut event_reader: EventReader<GameStateChange>,
     mut state: ResMut<NextState<GameState>>,
 ) {
     for event in event_reader.read() {
         match event {
-            GameStateChange::Staging => state.set(GameState::Staging),
+            // a run always ends on its summary before going back to staging
+            GameStateChange::Staging => state.set(GameState::GameOver),
             GameStateChange::MainGame => state.set(GameState::InGame),
         }
     }
@@ -181,6 +236,8 @@ enum GameState {
     #[default]
     Staging,
     InGame,
+    Paused,
+    GameOver,
 }
 
 #[derive(Event)]
@@ -192,20 +249,19 @@ pub enum GameStateChange {
 #[derive(Resource)]
 pub struct Gold(f32);
 
+// Meta-currency for the permanent upgrades, never reset between runs.
 #[derive(Resource)]
-pub struct UpgradeRadiusLvl(u32);
-
-#[derive(Resource)]
-pub struct AttackRadiusLvl(u32);
-
-#[derive(Resource)]
-pub struct DamageLvl(u32);
-
-#[derive(Resource)]
-pub struct GoldConversionRateLvl(u32);
+pub struct Diamonds(u32);
 
-pub fn calculate_cost_to_upgrade(level: u32) -> u32 {
-    level + (2 * level.ilog2())
+#[derive(Resource, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LifetimeStats {
+    pub runs: u32,
+    pub total_score: u64,
+    pub best_score: u32,
+    pub total_gold_earned: f32,
+    pub longest_run_secs: f32,
+    pub kills: u64,
 }
 /*
 Game Idea
@@ -259,6 +315,7 @@ fn setup(
         },
         RigidBody::Static,
         Collider::cuboid(20.0, 0.01, 20.0),
+        CollisionLayers::new([GameLayer::Ground], [GameLayer::Ground, GameLayer::Enemy]),
     ));
     // light
     commands.spawn(PointLightBundle {
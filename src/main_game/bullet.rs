@@ -1,157 +1,294 @@
-use crate::main_game::enemy::Enemy;
-use crate::main_game::tower::{Tower, TowerLevel};
-use crate::main_game::Health;
-use crate::{AttackRadiusLvl, DamageLvl, GameState, GameStateChange};
-use bevy::audio::{PlaybackMode, Volume};
+use crate::audio::{PlaySfx, Sfx};
+use crate::main_game::enemy::{Enemy, EnemyGrid, LastHitBy, Shield, Slowed, SpawnedAt};
+use crate::main_game::game_assets::GameAssets;
+use crate::main_game::mouse::MousePos;
+use crate::main_game::pool::EntityPool;
+use crate::main_game::tower::{
+    Destroyed, Projectile, TargetingPolicy, Tower, TowerKind, TowerLevel, TowerTypes,
+};
+use crate::main_game::{GameLayer, Health, RunGold, TickSet};
+use crate::upgrade::{Stat, Stats};
+use crate::GameStateChange;
 use bevy::prelude::*;
-use leafwing_input_manager::orientation::Orientation;
-use random_number::random;
-use std::ops::Mul;
+use bevy_xpbd_3d::prelude::*;
 
 pub struct BulletPlugin;
 
 impl Plugin for BulletPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(
-            Update,
-            spawn_bullet.run_if(state_exists_and_equals(GameState::InGame)),
+            FixedUpdate,
+            (spawn_bullet, steer_bullets, expire_bullets).in_set(TickSet::Gameplay),
         );
-        app.add_systems(Update, fly_to_enemy);
-        app.add_systems(Update, destroy_enemy);
+        app.add_systems(FixedUpdate, hit_enemies.in_set(TickSet::Collisions));
         app.add_systems(Update, despawn_if_game_changed);
     }
 }
 
+// a bullet that hasn't hit anything by then has missed
+const BULLET_LIFETIME_SECS: f32 = 3.0;
+// keeps weak, tiny bullets from slipping between enemies
+const MIN_BULLET_RADIUS: f32 = 0.03;
+
 #[derive(Component)]
 pub struct Bullet {
-    target: Entity,
+    // the tower that fired it
+    source: Entity,
+    // steered towards every tick while it lives, linear shots never have one
+    target: Option<Entity>,
     damage: f32,
+    speed: f32,
+    projectile: Projectile,
+    // enemies already hit, which chain and pierce bullets don't hit again
+    hit: Vec<Entity>,
+    secs_left: f32,
 }
 
 #[derive(Bundle)]
 pub struct BulletBundle {
     bullet: Bullet,
     pbr_bundle: PbrBundle,
+    // set along with the transform, a recycled bullet's body would otherwise start where it
+    // was parked
+    position: Position,
+    rigid_body: RigidBody,
+    collider: Collider,
+    sensor: Sensor,
+    collision_layers: CollisionLayers,
+    velocity: LinearVelocity,
 }
 
 fn spawn_bullet(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut pool: ResMut<EntityPool>,
+    game_assets: Res<GameAssets>,
     time: Res<Time>,
-    enemies: Query<(Entity, &Transform), With<Enemy>>,
-    mut towers: Query<(&Transform, &mut Tower, &TowerLevel), Without<Enemy>>,
-    asset_server: Res<AssetServer>,
-    attack_radius_lvl: Res<AttackRadiusLvl>,
-    damage_lvl: Res<DamageLvl>,
-    bevy_audio_sources: Query<Entity, With<Handle<AudioSource>>>,
+    enemy_grid: Res<EnemyGrid>,
+    enemies: Query<(&Health, &SpawnedAt), With<Enemy>>,
+    mut towers: Query<
+        (
+            Entity,
+            &Transform,
+            &mut Tower,
+            &TowerLevel,
+            &TowerKind,
+            &TargetingPolicy,
+        ),
+        (Without<Enemy>, Without<Destroyed>),
+    >,
+    tower_types: Res<TowerTypes>,
+    mouse_pos: Res<MousePos>,
+    mut run_gold: ResMut<RunGold>,
+    stats: Stats,
+    mut sfx: EventWriter<PlaySfx>,
 ) {
-    let attack_radius = attack_radius_lvl.0 as f32 / 15.0 + 1.0;
-    let damage = damage_lvl.0 as f32 / 30.0 + 0.2;
-    let mut number_of_shots = 0;
-    for (tower_pos, mut tower, tower_level) in towers.iter_mut() {
+    let attack_radius = stats.get(Stat::AttackRadius);
+    let damage = stats.get(Stat::Damage);
+    for (tower_entity, tower_pos, mut tower, tower_level, kind, policy) in towers.iter_mut() {
         tower.0.tick(time.delta());
-        if tower.0.finished() {
-            for (e, transform) in enemies.iter() {
-                let mut shoot = false;
-                if tower_pos.translation.distance(transform.translation) <= attack_radius {
-                    shoot = true;
-                    commands.spawn(BulletBundle {
-                        bullet: Bullet { target: e, damage },
-                        pbr_bundle: PbrBundle {
-                            mesh: meshes.add(
-                                shape::Icosphere {
-                                    radius: damage / 15.0,
-                                    subdivisions: 10,
-                                }
-                                .try_into()
-                                .unwrap(),
-                            ),
-                            material: materials
-                                .add(StandardMaterial::from(Color::rgb(1.0, 0.6, 0.1))),
-                            transform: Transform::default().with_translation(tower_pos.translation),
-                            ..default()
-                        },
-                    });
-                }
-                if shoot {
-                    number_of_shots += 1;
-                    if number_of_shots >= 6 {
-                        continue;
-                    }
-                    if bevy_audio_sources.iter().collect::<Vec<_>>().len() > 6 {
-                        continue;
-                    }
-                    commands.spawn(
-                        (AudioBundle {
-                            source: asset_server.load("shooting.ogg"),
-                            settings: PlaybackSettings {
-                                mode: PlaybackMode::Despawn,
-                                volume: Volume::new_relative(0.03),
-                                speed: 1.3,
-                                paused: false,
-                                spatial: false,
-                            },
-                        }),
-                    );
-                }
+        if !tower.0.finished() {
+            continue;
+        }
+        let Some(tower_type) = tower_types.get(&kind.0) else {
+            continue;
+        };
+        if let Projectile::Gold { amount } = tower_type.projectile {
+            run_gold.mines += amount * tower_level.0 as f32;
+            continue;
+        }
+        let range = attack_radius * tower_type.range;
+        let damage = damage * tower_type.damage;
+        let distance = |position: Vec3| tower_pos.translation.distance(position);
+        // lowest key is shot first
+        let mut in_range: Vec<(Entity, Vec3, f32)> = enemy_grid
+            .within(tower_pos.translation, range)
+            .filter(|(_, position)| distance(*position) <= range)
+            .filter_map(|(e, position)| {
+                let (health, spawned_at) = enemies.get(e).ok()?;
+                let key = match policy {
+                    TargetingPolicy::Nearest => distance(position),
+                    TargetingPolicy::Farthest => -distance(position),
+                    TargetingPolicy::LowestHealth => health.0,
+                    TargetingPolicy::HighestHealth => -health.0,
+                    TargetingPolicy::ClosestToCursor => position.distance(mouse_pos.0),
+                    TargetingPolicy::Oldest => spawned_at.0,
+                };
+                Some((e, position, key))
+            })
+            .collect();
+        in_range.sort_by(|(.., a), (.., b)| a.total_cmp(b));
+        in_range.truncate(tower_type.max_targets(tower_level.0));
+        for (e, position, _) in in_range {
+            let radius = damage / 15.0;
+            // homing bullets are re-aimed every tick, the rest keep this heading
+            let heading = (position - tower_pos.translation).normalize_or_zero();
+            pool.spawn_bullet(
+                &mut commands,
+                BulletBundle {
+                    bullet: Bullet {
+                        source: tower_entity,
+                        target: tower_type.projectile.is_homing().then_some(e),
+                        damage,
+                        speed: tower_type.projectile_speed,
+                        projectile: tower_type.projectile,
+                        hit: Vec::new(),
+                        secs_left: BULLET_LIFETIME_SECS,
+                    },
+                    pbr_bundle: PbrBundle {
+                        mesh: game_assets.bullet_mesh(radius),
+                        material: game_assets.bullet_material(&kind.0),
+                        transform: Transform::default().with_translation(tower_pos.translation),
+                        ..default()
+                    },
+                    position: Position(tower_pos.translation),
+                    rigid_body: RigidBody::Kinematic,
+                    collider: Collider::ball(radius.max(MIN_BULLET_RADIUS)),
+                    sensor: Sensor,
+                    collision_layers: CollisionLayers::new([GameLayer::Bullet], [GameLayer::Enemy]),
+                    velocity: LinearVelocity(heading * tower_type.projectile_speed),
+                },
+            );
+            sfx.send(PlaySfx(Sfx::Shoot));
+        }
+    }
+}
+
+fn steer_bullets(
+    mut bullets: Query<(&mut Bullet, &Transform, &mut LinearVelocity)>,
+    enemies: Query<&Transform, (With<Enemy>, Without<Bullet>)>,
+) {
+    for (mut bullet, bullet_pos, mut velocity) in bullets.iter_mut() {
+        let Some(target) = bullet.target else {
+            continue;
+        };
+        match enemies.get(target) {
+            Ok(target_pos) => {
+                let direction =
+                    (target_pos.translation - bullet_pos.translation).normalize_or_zero();
+                velocity.0 = direction * bullet.speed;
             }
+            // the target died first, fly on and hit whatever is in the way
+            Err(_) => bullet.target = None,
         }
     }
 }
 
-fn fly_to_enemy(
+fn hit_enemies(
     mut commands: Commands,
-    mut bullets: Query<(Entity, &Bullet, &mut Transform), Without<Enemy>>,
-    enemies: Query<&Transform, With<Enemy>>,
+    mut pool: ResMut<EntityPool>,
+    mut collisions: EventReader<CollisionStarted>,
+    mut bullets: Query<(&mut Bullet, &LinearVelocity)>,
+    mut enemies: Query<
+        (
+            &Transform,
+            &mut Health,
+            Option<&Shield>,
+            &mut LinearVelocity,
+        ),
+        (With<Enemy>, Without<Bullet>),
+    >,
+    enemy_grid: Res<EnemyGrid>,
 ) {
-    for (entity, bullet, mut bullet_pos) in bullets.iter_mut() {
-        if let Ok(target_enemy) = enemies.get(bullet.target) {
-            let mut direction = target_enemy.translation - bullet_pos.translation;
-            direction = direction.normalize_or_zero();
-            bullet_pos.translation += direction.mul(Vec3::splat(0.1));
+    // several collisions can start in the same step, a bullet is only used up once
+    let mut spent = Vec::new();
+    for CollisionStarted(a, b) in collisions.read() {
+        let (bullet_entity, enemy) = if bullets.contains(*a) {
+            (*a, *b)
         } else {
-            commands.entity(entity).despawn();
+            (*b, *a)
+        };
+        let Ok((mut bullet, bullet_velocity)) = bullets.get_mut(bullet_entity) else {
+            continue;
+        };
+        if spent.contains(&bullet_entity) || bullet.hit.contains(&enemy) {
+            continue;
+        }
+        let Ok((enemy_pos, ..)) = enemies.get(enemy) else {
+            continue;
+        };
+        let impact = enemy_pos.translation;
+        let victims: Vec<Entity> = match bullet.projectile {
+            Projectile::Splash { radius } => {
+                enemy_grid.within(impact, radius).map(|(e, _)| e).collect()
+            }
+            _ => vec![enemy],
+        };
+        for victim in victims {
+            let Ok((_, mut health, shield, _)) = enemies.get_mut(victim) else {
+                continue;
+            };
+            if shield.is_some() {
+                commands.entity(victim).remove::<Shield>();
+            } else {
+                health.0 -= bullet.damage;
+                commands.entity(victim).insert(LastHitBy(bullet.source));
+            }
         }
+        bullet.hit.push(enemy);
+        match bullet.projectile {
+            Projectile::Frost { slow, secs } => {
+                commands
+                    .entity(enemy)
+                    .try_insert(Slowed { factor: slow, secs });
+            }
+            Projectile::Chain {
+                jumps,
+                range,
+                falloff,
+            } if bullet.hit.len() as u32 <= jumps => {
+                let next = enemy_grid
+                    .within(impact, range)
+                    .filter(|(e, _)| !bullet.hit.contains(e))
+                    .map(|(e, position)| (e, position.distance(impact)))
+                    .filter(|(_, distance)| *distance <= range)
+                    .min_by(|a, b| a.1.total_cmp(&b.1));
+                if let Some((next, _)) = next {
+                    bullet.target = Some(next);
+                    bullet.damage *= falloff;
+                    continue;
+                }
+            }
+            Projectile::Pierce { enemies } if (bullet.hit.len() as u32) < enemies => continue,
+            Projectile::Knockback { push } => {
+                let direction =
+                    Vec3::new(bullet_velocity.0.x, 0.0, bullet_velocity.0.z).normalize_or_zero();
+                if let Ok((.., mut velocity)) = enemies.get_mut(enemy) {
+                    velocity.0 += direction * push;
+                }
+            }
+            _ => {}
+        }
+        spent.push(bullet_entity);
+        pool.recycle_bullet(&mut commands, bullet_entity);
     }
 }
 
-fn destroy_enemy(
+fn expire_bullets(
     mut commands: Commands,
-    bullets: Query<(Entity, &Bullet, &Transform), Without<Enemy>>,
-    mut enemies: Query<(&Transform, &mut Health), With<Enemy>>,
-    asset_server: Res<AssetServer>,
+    mut pool: ResMut<EntityPool>,
+    time: Res<Time>,
+    mut bullets: Query<(Entity, &mut Bullet)>,
 ) {
-    const DESTROY_DISTANCE: f32 = 0.05;
-    for (bullet_entity, bullet, bullet_pos) in bullets.iter() {
-        if let Ok((target_transform, mut health)) = enemies.get_mut(bullet.target) {
-            if target_transform
-                .translation
-                .distance(bullet_pos.translation)
-                <= DESTROY_DISTANCE
-            {
-                health.0 -= bullet.damage;
-                commands.entity(bullet_entity).despawn();
-            }
+    for (entity, mut bullet) in bullets.iter_mut() {
+        bullet.secs_left -= time.delta_seconds();
+        if bullet.secs_left <= 0.0 {
+            pool.recycle_bullet(&mut commands, entity);
         }
     }
 }
 
+// Bullets still flying when a run ends would otherwise carry on through game over and staging.
+// The pool is emptied for the new run as well, so these are despawned rather than parked.
 fn despawn_if_game_changed(
     mut commands: Commands,
     mut event_reader: EventReader<GameStateChange>,
     query: Query<Entity, With<Bullet>>,
 ) {
-    for ev in event_reader.read() {
-        match ev {
-            GameStateChange::Staging => {}
-            GameStateChange::MainGame => {
-                for q in query.iter() {
-                    commands.entity(q).despawn();
-                }
-                break;
-            }
-        }
+    if event_reader.is_empty() {
+        return;
     }
     event_reader.clear();
+    for q in query.iter() {
+        commands.entity(q).despawn();
+    }
 }
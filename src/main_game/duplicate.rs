@@ -0,0 +1,91 @@
+use crate::main_game::enemy::{Enemy, EnemyKind, MaxHealth, Shield};
+use crate::main_game::input::PlayerAction;
+use crate::main_game::mouse::MousePos;
+use crate::main_game::replay::TickInput;
+use crate::main_game::wave::{SpawnEnemy, WaveMember};
+use crate::main_game::{Health, Speed, TickSet};
+use crate::GameStateChange;
+use bevy::prelude::*;
+
+const DUPLICATE_RADIUS: f32 = 0.6;
+const DUPLICATE_COOLDOWN_SECS: f32 = 3.0;
+
+pub struct DuplicatePlugin;
+
+impl Plugin for DuplicatePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(DuplicateCooldown::default());
+        app.add_systems(Update, reset_cooldown_on_game_start);
+        app.add_systems(FixedUpdate, duplicate_enemies.in_set(TickSet::Gameplay));
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct DuplicateCooldown(pub f32);
+
+fn reset_cooldown_on_game_start(
+    mut event_reader: EventReader<GameStateChange>,
+    mut cooldown: ResMut<DuplicateCooldown>,
+) {
+    for ev in event_reader.read() {
+        if let GameStateChange::MainGame = ev {
+            cooldown.0 = 0.0;
+        }
+    }
+}
+
+// Every enemy near the cursor splits in two: the original slows to half speed and the copy
+// moves at double speed.
+pub(crate) fn duplicate_enemies(
+    tick_input: Res<TickInput>,
+    mouse_pos: Res<MousePos>,
+    time: Res<Time>,
+    mut cooldown: ResMut<DuplicateCooldown>,
+    mut enemies: Query<
+        (
+            &Transform,
+            &EnemyKind,
+            &mut Speed,
+            &Health,
+            &MaxHealth,
+            &WaveMember,
+            Option<&Shield>,
+        ),
+        With<Enemy>,
+    >,
+    mut spawn_events: EventWriter<SpawnEnemy>,
+) {
+    if cooldown.0 > 0.0 {
+        cooldown.0 -= time.delta_seconds();
+        return;
+    }
+    if !tick_input.just_pressed(PlayerAction::Duplicate) {
+        return;
+    }
+    let mouse = mouse_pos.0.xz();
+    let mut duplicated = false;
+    for (transform, kind, mut speed, health, max_health, wave_member, shield) in enemies.iter_mut()
+    {
+        let position = transform.translation.xz();
+        if position.distance(mouse) > DUPLICATE_RADIUS {
+            continue;
+        }
+        let original_speed = speed.0;
+        speed.0 = original_speed / 2.0;
+        let away_from_cursor = (position - mouse).normalize_or_zero();
+        spawn_events.send(SpawnEnemy {
+            kind: kind.0.clone(),
+            position: position + away_from_cursor * 0.1,
+            wave: wave_member.0,
+            speed: Some(original_speed * 2.0),
+            health: Some(health.0),
+            max_health: Some(max_health.0),
+            // a broken shield stays broken on the copy
+            shielded: Some(shield.is_some()),
+        });
+        duplicated = true;
+    }
+    if duplicated {
+        cooldown.0 = DUPLICATE_COOLDOWN_SECS;
+    }
+}
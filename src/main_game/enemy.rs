@@ -1,157 +1,432 @@
+use crate::audio::{PlaySfx, Sfx};
+use crate::main_game::duplicate::duplicate_enemies;
+use crate::main_game::game_assets::GameAssets;
+use crate::main_game::goal::TimeGoal;
 use crate::main_game::mouse::MousePos;
-use crate::main_game::tower::TimeSinceGameStart;
-use crate::main_game::{Health, Score, Speed};
+use crate::main_game::pool::EntityPool;
+use crate::main_game::tower::{
+    Destroyed, RunTowers, TimeSinceGameStart, Tower, TowerId, TowerKills,
+};
+use crate::main_game::wave::{direct_waves, SpawnEnemy, WaveMember};
+use crate::main_game::{GameLayer, Health, RunGold, RunKills, Score, Speed, TickSet};
+use crate::ron_asset::RonAssetPlugin;
+use crate::spatial::SpatialGrid;
+use crate::upgrade::Stats;
 use crate::GameState;
-use bevy::audio::{PlaybackMode, Volume};
 use bevy::prelude::*;
 use bevy_xpbd_3d::prelude::*;
-use random_number::random;
+use serde::Deserialize;
+use std::f32::consts::TAU;
 use std::ops::Mul;
 
+// converts an archetype's speed into the impulse applied each tick
+const IMPULSE_PER_SPEED: f32 = 0.006;
+// how close an enemy has to get to a tower to damage it
+const TOWER_CONTACT_DISTANCE: f32 = 0.35;
+
 pub struct EnemyPlugin;
 impl Plugin for EnemyPlugin {
     fn build(&self, app: &mut App) {
+        app.add_plugins(RonAssetPlugin::<EnemyArchetypes>::new(
+            "default.enemies.ron",
+            &["enemies.ron"],
+            include_str!("../../assets/default.enemies.ron"),
+        ));
         app.add_systems(
-            Update,
-            (move_enemy_to_mouse, set_color_to_health, spawn_enemies)
-                .run_if(state_exists_and_equals(GameState::InGame)),
+            FixedUpdate,
+            (
+                (choose_target, move_enemy_to_target, damage_towers).chain(),
+                wear_off_slow,
+                if_health_below_zero_then_die,
+                // spawns everything requested this tick, splits included
+                spawn_enemies
+                    .after(direct_waves)
+                    .after(duplicate_enemies)
+                    .after(if_health_below_zero_then_die),
+            )
+                .in_set(TickSet::Gameplay),
         );
         app.add_systems(
-            PostUpdate,
-            if_health_below_zero_then_die.run_if(state_exists_and_equals(GameState::InGame)),
+            Update,
+            set_color_to_health.run_if(state_exists_and_equals(GameState::InGame)),
         );
+        app.insert_resource(EnemyGrid(SpatialGrid::new(ENEMY_GRID_CELL_SIZE)));
+        app.add_systems(FixedUpdate, rebuild_enemy_grid.in_set(TickSet::Input));
     }
 }
 
 #[derive(Component)]
 pub struct Enemy;
 
+// About a tower's base attack radius, so most range queries touch only a few cells.
+const ENEMY_GRID_CELL_SIZE: f32 = 0.5;
+
+// Enemy positions as of the start of the tick, for range queries that would otherwise scan
+// every enemy.
+#[derive(Resource, Deref)]
+pub struct EnemyGrid(SpatialGrid);
+
+#[derive(Component)]
+pub struct EnemyKind(pub String);
+
+// Enemies chase the cursor while it is within their aggro radius and go after the nearest tower
+// otherwise.
+#[derive(Component, Default, Clone, Copy, PartialEq)]
+pub enum EnemyTarget {
+    #[default]
+    Cursor,
+    Tower(Entity),
+}
+
+// Absorbs the first hit and is then removed.
+#[derive(Component)]
+pub struct Shield;
+
+// Run time the enemy appeared at, for towers that shoot the oldest enemy first.
+#[derive(Component)]
+pub struct SpawnedAt(pub f32);
+
+// Health the enemy spawned with, after difficulty and overtime scaling.
+#[derive(Component)]
+pub struct MaxHealth(pub f32);
+
+// The tower whose bullet last damaged the enemy, credited with the kill if it dies.
+#[derive(Component)]
+pub struct LastHitBy(pub Entity);
+
+// Left by frost towers, multiplies the enemy's speed until it wears off.
+#[derive(Component)]
+pub struct Slowed {
+    pub factor: f32,
+    pub secs: f32,
+}
+
+#[derive(Asset, Resource, TypePath, Deserialize, Clone)]
+pub struct EnemyArchetypes {
+    pub archetypes: Vec<EnemyArchetype>,
+}
+
+impl EnemyArchetypes {
+    pub fn get(&self, id: &str) -> Option<&EnemyArchetype> {
+        self.archetypes.iter().find(|archetype| archetype.id == id)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct EnemyArchetype {
+    pub id: String,
+    pub shape: EnemyShape,
+    pub size: f32,
+    pub color: (f32, f32, f32),
+    pub health: f32,
+    pub speed: f32,
+    pub score: u32,
+    pub gold_bounty: f32,
+    #[serde(default = "default_aggro_radius")]
+    pub aggro_radius: f32,
+    #[serde(default = "default_tower_damage")]
+    pub tower_damage: f32,
+    #[serde(default)]
+    pub shielded: bool,
+    #[serde(default)]
+    pub split: Option<Split>,
+}
+
+fn default_aggro_radius() -> f32 {
+    3.0
+}
+
+fn default_tower_damage() -> f32 {
+    0.5
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub enum EnemyShape {
+    Cube,
+    Sphere,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Split {
+    pub into: String,
+    pub count: u32,
+}
+
+impl EnemyArchetype {
+    pub fn color(&self) -> Color {
+        Color::rgb(self.color.0, self.color.1, self.color.2)
+    }
+
+    pub fn mesh(&self) -> Mesh {
+        match self.shape {
+            EnemyShape::Cube => shape::Cube::new(self.size).into(),
+            EnemyShape::Sphere => shape::UVSphere {
+                radius: self.size / 2.0,
+                ..default()
+            }
+            .into(),
+        }
+    }
+
+    fn collider(&self) -> Collider {
+        match self.shape {
+            EnemyShape::Cube => Collider::cuboid(self.size, self.size, self.size),
+            EnemyShape::Sphere => Collider::ball(self.size / 2.0),
+        }
+    }
+}
+
 #[derive(Bundle)]
 pub struct EnemyBundle {
     pub enemy: Enemy,
     pub speed: Speed,
     pub pbr_bundle: PbrBundle,
+    // set along with the transform, a recycled enemy's body would otherwise start where it died
+    pub position: Position,
     pub rigid_body: RigidBody,
     pub angular_velocity: AngularVelocity,
     pub collider: Collider,
+    pub collision_layers: CollisionLayers,
     pub friction: Friction,
     pub health: Health,
 }
 
-fn move_enemy_to_mouse(
+fn rebuild_enemy_grid(
+    mut grid: ResMut<EnemyGrid>,
+    enemies: Query<(Entity, &Transform), With<Enemy>>,
+) {
+    grid.0.clear();
+    for (enemy, transform) in enemies.iter() {
+        grid.0.insert(enemy, transform.translation);
+    }
+}
+
+fn choose_target(
+    mouse_pos: Res<MousePos>,
+    archetypes: Res<EnemyArchetypes>,
+    mut enemies: Query<(&Transform, &EnemyKind, &mut EnemyTarget), With<Enemy>>,
+    towers: Query<(Entity, &Transform), (With<Tower>, Without<Destroyed>)>,
+) {
+    let mouse = mouse_pos.0.xz();
+    for (enemy_pos, kind, mut target) in enemies.iter_mut() {
+        let position = enemy_pos.translation.xz();
+        let aggro_radius = archetypes
+            .get(&kind.0)
+            .map_or(default_aggro_radius(), |archetype| archetype.aggro_radius);
+        let new_target = if position.distance(mouse) <= aggro_radius {
+            EnemyTarget::Cursor
+        } else {
+            towers
+                .iter()
+                .min_by(|(_, a), (_, b)| {
+                    let a = a.translation.xz().distance_squared(position);
+                    let b = b.translation.xz().distance_squared(position);
+                    a.total_cmp(&b)
+                })
+                .map_or(EnemyTarget::Cursor, |(tower, _)| EnemyTarget::Tower(tower))
+        };
+        if *target != new_target {
+            *target = new_target;
+        }
+    }
+}
+
+fn move_enemy_to_target(
     mut commands: Commands,
     mouse_pos: Res<MousePos>,
-    mut enemies: Query<(Entity, &Transform), With<Enemy>>,
+    mut enemies: Query<(Entity, &Transform, &Speed, &EnemyTarget, Option<&Slowed>), With<Enemy>>,
+    towers: Query<&Transform, With<Tower>>,
 ) {
-    for (enemy, enemy_pos) in enemies.iter_mut() {
-        let mut direction = mouse_pos.0 - enemy_pos.translation;
+    for (enemy, enemy_pos, speed, target, slowed) in enemies.iter_mut() {
+        let target_pos = match target {
+            EnemyTarget::Cursor => mouse_pos.0,
+            EnemyTarget::Tower(tower) => match towers.get(*tower) {
+                Ok(tower_pos) => tower_pos.translation,
+                Err(_) => continue,
+            },
+        };
+        let mut direction = target_pos - enemy_pos.translation;
         direction.y = 0.0;
         direction = direction.normalize_or_zero();
-        commands
-            .entity(enemy)
-            .try_insert(ExternalImpulse::new(direction.mul(0.0003)));
+        commands.entity(enemy).try_insert(ExternalImpulse::new(
+            direction.mul(speed.0 * slowed.map_or(1.0, |s| s.factor) * IMPULSE_PER_SPEED),
+        ));
     }
 }
 
+fn wear_off_slow(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut enemies: Query<(Entity, &mut Slowed)>,
+) {
+    for (enemy, mut slowed) in enemies.iter_mut() {
+        slowed.secs -= time.delta_seconds();
+        if slowed.secs <= 0.0 {
+            commands.entity(enemy).remove::<Slowed>();
+        }
+    }
+}
+
+fn damage_towers(
+    time: Res<Time>,
+    archetypes: Res<EnemyArchetypes>,
+    enemies: Query<(&Transform, &EnemyKind, &EnemyTarget), With<Enemy>>,
+    mut towers: Query<(&Transform, &mut Health), (With<Tower>, Without<Destroyed>, Without<Enemy>)>,
+) {
+    for (enemy_pos, kind, target) in enemies.iter() {
+        let EnemyTarget::Tower(tower) = target else {
+            continue;
+        };
+        let Ok((tower_pos, mut health)) = towers.get_mut(*tower) else {
+            continue;
+        };
+        if tower_pos
+            .translation
+            .xz()
+            .distance(enemy_pos.translation.xz())
+            > TOWER_CONTACT_DISTANCE
+        {
+            continue;
+        }
+        if let Some(archetype) = archetypes.get(&kind.0) {
+            health.0 -= archetype.tower_damage * time.delta_seconds();
+        }
+    }
+}
+
+// Swaps between the shared shades rather than editing a material per enemy.
 fn set_color_to_health(
-    enemies: Query<(&Health, &Handle<StandardMaterial>), With<Enemy>>,
-    mut standard_material: ResMut<Assets<StandardMaterial>>,
+    mut enemies: Query<
+        (
+            &Health,
+            &MaxHealth,
+            &EnemyKind,
+            &mut Handle<StandardMaterial>,
+        ),
+        With<Enemy>,
+    >,
+    game_assets: Res<GameAssets>,
 ) {
-    for (health, handle) in enemies.iter() {
-        standard_material
-            .get_mut(handle)
-            .unwrap()
-            .base_color
-            .set_b(health.0);
+    for (health, max_health, kind, mut handle) in enemies.iter_mut() {
+        let material = game_assets.enemy_material(&kind.0, health.0 / max_health.0);
+        if *handle != material {
+            *handle = material;
+        }
     }
 }
 
 fn if_health_below_zero_then_die(
-    mut enemies: Query<(Entity, &Transform, &Health), With<Enemy>>,
+    mut enemies: Query<
+        (
+            Entity,
+            &Transform,
+            &Health,
+            &EnemyKind,
+            &WaveMember,
+            Option<&LastHitBy>,
+        ),
+        With<Enemy>,
+    >,
+    mut tower_kills: Query<(&mut TowerKills, &TowerId)>,
+    mut run_towers: ResMut<RunTowers>,
     mut commands: Commands,
+    mut pool: ResMut<EntityPool>,
     mut score: ResMut<Score>,
-    asset_server: Res<AssetServer>,
-    bevy_audio_sources: Query<Entity, With<Handle<AudioSource>>>,
+    mut run_gold: ResMut<RunGold>,
+    mut run_kills: ResMut<RunKills>,
+    archetypes: Res<EnemyArchetypes>,
+    mut spawn_events: EventWriter<SpawnEnemy>,
+    mut sfx: EventWriter<PlaySfx>,
 ) {
-    let mut num_die = 0;
-    for (enemy, transform, health) in enemies.iter_mut() {
+    for (enemy, transform, health, kind, wave_member, last_hit_by) in enemies.iter_mut() {
         if health.0 <= 0.0 {
-            score.0 += 1;
-            commands.entity(enemy).despawn();
-            if num_die != 0 {
-                continue;
+            pool.recycle_enemy(&mut commands, enemy);
+            run_kills.0 += 1;
+            // the tower may have been sold or destroyed since it fired
+            if let Some((mut kills, id)) = last_hit_by.and_then(|by| tower_kills.get_mut(by.0).ok())
+            {
+                kills.0 += 1;
+                if let Some(summary) = run_towers.0.get_mut(id) {
+                    summary.kills = kills.0;
+                }
             }
-            if bevy_audio_sources.iter().collect::<Vec<_>>().len() > 6 {
-                continue;
+            if let Some(archetype) = archetypes.get(&kind.0) {
+                score.0 += archetype.score;
+                run_gold.bounties += archetype.gold_bounty;
+                if let Some(split) = &archetype.split {
+                    let position = transform.translation.xz();
+                    for i in 0..split.count {
+                        let angle = i as f32 / split.count as f32 * TAU;
+                        spawn_events.send(SpawnEnemy {
+                            kind: split.into.clone(),
+                            position: position + Vec2::new(angle.cos(), angle.sin()) * 0.1,
+                            wave: wave_member.0,
+                            speed: None,
+                            health: None,
+                            max_health: None,
+                            shielded: None,
+                        });
+                    }
+                }
             }
-            num_die += 1;
-            commands.spawn((
-                AudioBundle {
-                    source: asset_server.load("enemy_death.ogg"),
-                    settings: PlaybackSettings {
-                        mode: PlaybackMode::Despawn,
-                        volume: Volume::new_relative(0.1),
-                        speed: 1.3,
-                        paused: false,
-                        spatial: false,
-                    },
-                },
-                transform.clone(),
-                GlobalTransform::default(),
-            ));
+            sfx.send(PlaySfx(Sfx::EnemyDeath));
         }
     }
 }
 
 fn spawn_enemies(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    time: Res<Time>,
+    mut pool: ResMut<EntityPool>,
+    game_assets: Res<GameAssets>,
+    archetypes: Res<EnemyArchetypes>,
     time_since_game_start: Res<TimeSinceGameStart>,
+    time_goal: Res<TimeGoal>,
+    stats: Stats,
+    mut spawn_events: EventReader<SpawnEnemy>,
 ) {
-    let val: f32 = random!();
-
-    let mut chance = (time_since_game_start.0 + 2.0) / 400.0;
-
-    if time_since_game_start.0 < 100.0 {
-        chance = (time_since_game_start.0 + 2.0) / 1000.0;
-    }
-    if val > chance {
-        return;
-    }
-    let mut x: f32 = random!();
-    let mut z: f32 = random!();
-    x -= 0.5;
-    z -= 0.5;
-    x *= 2.0;
-    z *= 2.0;
-    let mut thing = Vec2::new(x, z);
-    let (x_neg, y_neg): (f32, f32) = (random!(), random!());
-    let x_neg = if x_neg < 0.5 { -0.01 } else { 0.01 };
-    let y_neg = if y_neg < 0.5 { -0.01 } else { 0.01 };
-    let val: f32 = random!();
-    while thing.distance(Vec2::default()) < 7.0 {
-        if val < 0.5 {
-            thing.x += x_neg;
-        } else {
-            thing.y += y_neg;
+    // overrides come from an existing enemy and are already scaled
+    let modifiers = stats.modifiers();
+    let health_scale = modifiers.enemy_health * time_goal.health_scale(time_since_game_start.0);
+    let speed_scale = modifiers.enemy_speed * time_goal.speed_scale(time_since_game_start.0);
+    for spawn in spawn_events.read() {
+        let Some(archetype) = archetypes.get(&spawn.kind) else {
+            warn!("unknown enemy kind {}", spawn.kind);
+            continue;
+        };
+        let translation = Vec3::new(spawn.position.x, 0.2, spawn.position.y);
+        let max_health = spawn.max_health.unwrap_or(archetype.health * health_scale);
+        let mut enemy = pool.spawn_enemy(
+            &mut commands,
+            (
+                EnemyBundle {
+                    enemy: Enemy,
+                    speed: Speed(spawn.speed.unwrap_or(archetype.speed * speed_scale)),
+                    pbr_bundle: PbrBundle {
+                        mesh: game_assets.enemy_mesh(&archetype.id),
+                        material: game_assets.enemy_material(&archetype.id, 1.0),
+                        transform: Transform::from_translation(translation),
+                        ..default()
+                    },
+                    position: Position(translation),
+                    rigid_body: RigidBody::Dynamic,
+                    angular_velocity: Default::default(),
+                    collider: archetype.collider(),
+                    collision_layers: CollisionLayers::new(
+                        [GameLayer::Enemy],
+                        [GameLayer::Ground, GameLayer::Enemy, GameLayer::Bullet],
+                    ),
+                    friction: Friction::new(0.00),
+                    health: Health(spawn.health.unwrap_or(max_health)),
+                },
+                MaxHealth(max_health),
+                EnemyKind(archetype.id.clone()),
+                EnemyTarget::default(),
+                WaveMember(spawn.wave),
+                SpawnedAt(time_since_game_start.0),
+            ),
+        );
+        if spawn.shielded.unwrap_or(archetype.shielded) {
+            enemy.insert(Shield);
         }
     }
-    (x, z) = (thing.x, thing.y);
-    commands.spawn(EnemyBundle {
-        enemy: Enemy,
-        speed: Speed(0.05),
-        pbr_bundle: PbrBundle {
-            mesh: meshes.add(shape::Cube::new(0.1).into()),
-            material: materials.add(Color::rgb(0.3, 0.3, 1.0).into()),
-            transform: Transform::from_xyz(x, 0.2, z),
-            ..default()
-        },
-        rigid_body: RigidBody::Dynamic,
-        angular_velocity: Default::default(),
-        collider: Collider::cuboid(0.1, 0.1, 0.1),
-        friction: Friction::new(0.00),
-        health: Health(1.0),
-    });
 }
@@ -0,0 +1,129 @@
+use crate::main_game::enemy::EnemyArchetypes;
+use crate::main_game::tower::TowerTypes;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+// bullet radii grow by `BULLET_TIER_RATIO` from the smallest, rounded up to the next tier
+const BULLET_TIERS: usize = 10;
+const SMALLEST_BULLET_RADIUS: f32 = 0.01;
+const BULLET_TIER_RATIO: f32 = 1.5;
+// enemies darken in this many steps as they lose health
+const HEALTH_SHADES: usize = 8;
+
+pub struct GameAssetsPlugin;
+
+impl Plugin for GameAssetsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameAssets>();
+        app.add_systems(Startup, build_bullet_meshes);
+        // also picks up hot reloaded tower and enemy definitions
+        app.add_systems(
+            PreUpdate,
+            build_per_kind_assets.run_if(
+                resource_changed::<TowerTypes>().or_else(resource_changed::<EnemyArchetypes>()),
+            ),
+        );
+    }
+}
+
+// Meshes and materials shared by every bullet and enemy, so spawning one doesn't add assets.
+#[derive(Resource, Default)]
+pub struct GameAssets {
+    bullet_meshes: Vec<(f32, Handle<Mesh>)>,
+    // by tower type id
+    bullet_materials: HashMap<String, Handle<StandardMaterial>>,
+    // by enemy archetype id
+    enemy_meshes: HashMap<String, Handle<Mesh>>,
+    // by enemy archetype id, from the darkest shade to full health
+    enemy_materials: HashMap<String, Vec<Handle<StandardMaterial>>>,
+}
+
+impl GameAssets {
+    // The smallest bullet mesh with at least this radius. Only the look is rounded up, colliders
+    // keep the exact radius.
+    pub fn bullet_mesh(&self, radius: f32) -> Handle<Mesh> {
+        let (_, mesh) = self
+            .bullet_meshes
+            .iter()
+            .find(|(tier_radius, _)| *tier_radius >= radius)
+            .or(self.bullet_meshes.last())
+            .expect("bullet meshes are built at startup");
+        mesh.clone()
+    }
+
+    pub fn bullet_material(&self, tower_kind: &str) -> Handle<StandardMaterial> {
+        self.bullet_materials
+            .get(tower_kind)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn enemy_mesh(&self, enemy_kind: &str) -> Handle<Mesh> {
+        self.enemy_meshes
+            .get(enemy_kind)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn enemy_material(
+        &self,
+        enemy_kind: &str,
+        health_fraction: f32,
+    ) -> Handle<StandardMaterial> {
+        let Some(shades) = self.enemy_materials.get(enemy_kind) else {
+            return Handle::default();
+        };
+        let shade = (health_fraction.clamp(0.0, 1.0) * (shades.len() - 1) as f32).round();
+        shades[shade as usize].clone()
+    }
+}
+
+fn build_bullet_meshes(mut game_assets: ResMut<GameAssets>, mut meshes: ResMut<Assets<Mesh>>) {
+    game_assets.bullet_meshes = (0..BULLET_TIERS)
+        .map(|tier| {
+            let radius = SMALLEST_BULLET_RADIUS * BULLET_TIER_RATIO.powi(tier as i32);
+            let mesh = shape::Icosphere {
+                radius,
+                subdivisions: 10,
+            }
+            .try_into()
+            .unwrap();
+            (radius, meshes.add(mesh))
+        })
+        .collect();
+}
+
+fn build_per_kind_assets(
+    mut game_assets: ResMut<GameAssets>,
+    tower_types: Res<TowerTypes>,
+    archetypes: Res<EnemyArchetypes>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    game_assets.bullet_materials = tower_types
+        .towers
+        .iter()
+        .map(|tower_type| {
+            let material = materials.add(tower_type.projectile_color().into());
+            (tower_type.id.clone(), material)
+        })
+        .collect();
+    game_assets.enemy_meshes = archetypes
+        .archetypes
+        .iter()
+        .map(|archetype| (archetype.id.clone(), meshes.add(archetype.mesh())))
+        .collect();
+    game_assets.enemy_materials = archetypes
+        .archetypes
+        .iter()
+        .map(|archetype| {
+            let shades = (0..HEALTH_SHADES)
+                .map(|shade| {
+                    let fraction = shade as f32 / (HEALTH_SHADES - 1) as f32;
+                    materials.add((archetype.color() * (0.3 + 0.7 * fraction)).into())
+                })
+                .collect();
+            (archetype.id.clone(), shades)
+        })
+        .collect();
+}
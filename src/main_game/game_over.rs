@@ -0,0 +1,125 @@
+use crate::GameState;
+use bevy::prelude::*;
+use bevy_egui::EguiContexts;
+
+pub struct GameOverPlugin;
+
+impl Plugin for GameOverPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            game_over_ui.run_if(state_exists_and_equals(GameState::GameOver)),
+        );
+    }
+}
+
+// How the last run went, filled in by `on_die` as the run ends.
+#[derive(Resource, Default)]
+pub struct RunSummary {
+    pub score: u32,
+    pub survival_secs: f32,
+    pub time_goal_secs: f32,
+    pub goal_reached: bool,
+    pub towers_placed: u32,
+    pub highest_tower_level: u32,
+    // every tower placed during the run, most kills first
+    pub towers: Vec<TowerSummary>,
+    pub conversion_rate: f32,
+    pub goal_multiplier: f32,
+    // from the difficulty and mutators
+    pub difficulty_multiplier: f32,
+    pub gold_from_score: f32,
+    // picked up during the run
+    pub gold_from_bounties: f32,
+    pub gold_from_mines: f32,
+    pub gold_from_refunds: f32,
+    // all of the above
+    pub gold_earned: f32,
+    pub diamonds_from_gold: u32,
+    pub diamonds_from_kills: u32,
+    pub gold_left: f32,
+    pub new_best: bool,
+}
+
+#[derive(Clone)]
+pub struct TowerSummary {
+    pub name: String,
+    pub level: u32,
+    pub kills: u32,
+}
+
+fn game_over_ui(
+    mut contexts: EguiContexts,
+    summary: Res<RunSummary>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let ctx = contexts.ctx_mut();
+    egui::Window::new("game over")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(ctx, |ui| {
+            if summary.new_best {
+                ui.heading(format!("new best: {}", summary.score));
+            } else {
+                ui.heading(format!("score: {}", summary.score));
+            }
+            let reached = if summary.goal_reached {
+                "reached"
+            } else {
+                "missed"
+            };
+            ui.label(format!(
+                "survived {:.2} of {:.3} minutes, goal {}",
+                summary.survival_secs / 60.0,
+                summary.time_goal_secs / 60.0,
+                reached
+            ));
+            ui.label(format!("towers placed: {}", summary.towers_placed));
+            ui.label(format!(
+                "highest tower level: {}",
+                summary.highest_tower_level
+            ));
+            egui::CollapsingHeader::new("kills per tower").show(ui, |ui| {
+                egui::Grid::new("tower_kills").show(ui, |ui| {
+                    for tower in summary.towers.iter() {
+                        ui.label(format!("{} level {}", tower.name, tower.level));
+                        ui.label(tower.kills.to_string());
+                        ui.end_row();
+                    }
+                });
+            });
+            ui.separator();
+            egui::Grid::new("gold_earned").show(ui, |ui| {
+                ui.label(format!(
+                    "{} score x {} x {} goal bonus x {} difficulty",
+                    summary.score,
+                    summary.conversion_rate,
+                    summary.goal_multiplier,
+                    summary.difficulty_multiplier
+                ));
+                ui.label(format!("{:.1}", summary.gold_from_score));
+                ui.end_row();
+                for (source, gold) in [
+                    ("enemy bounties", summary.gold_from_bounties),
+                    ("gold mines", summary.gold_from_mines),
+                    ("towers sold", summary.gold_from_refunds),
+                ] {
+                    ui.label(source);
+                    ui.label(format!("{:.1}", gold));
+                    ui.end_row();
+                }
+                ui.label("gold earned");
+                ui.label(format!("{:.1}", summary.gold_earned));
+                ui.end_row();
+            });
+            ui.label(format!(
+                "diamonds: {} from gold, {} from kills",
+                summary.diamonds_from_gold, summary.diamonds_from_kills
+            ));
+            ui.label(format!("gold left over: {:.1}", summary.gold_left));
+            if ui.button("continue").clicked() {
+                next_state.set(GameState::Staging);
+            }
+        });
+}
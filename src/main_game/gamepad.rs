@@ -0,0 +1,250 @@
+use crate::main_game::mouse::MousePos;
+use crate::main_game::replay::live_input;
+use crate::GameState;
+use bevy::prelude::*;
+
+const STICK_DEAD_ZONE: f32 = 0.15;
+// world units per second, ramping from base to max while the stick stays pushed
+const CURSOR_BASE_SPEED: f32 = 1.5;
+const CURSOR_MAX_SPEED: f32 = 6.0;
+const CURSOR_ACCELERATION_SECS: f32 = 0.6;
+// half the size of the ground plane
+const CURSOR_BOUNDS: f32 = 10.0;
+
+pub struct GamepadCursorPlugin;
+
+impl Plugin for GamepadCursorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GamepadCursor>();
+        app.add_systems(Startup, spawn_cursor_marker);
+        app.add_systems(
+            Update,
+            move_cursor_with_gamepad
+                .run_if(state_exists_and_equals(GameState::InGame).and_then(live_input)),
+        );
+        app.add_systems(Update, update_cursor_marker.after(move_cursor_with_gamepad));
+    }
+}
+
+#[derive(Resource, Default)]
+struct GamepadCursor {
+    held_secs: f32,
+}
+
+// Stands in for the mouse pointer when a gamepad is driving the cursor.
+#[derive(Component)]
+struct CursorMarker;
+
+fn spawn_cursor_marker(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(
+                shape::UVSphere {
+                    radius: 0.04,
+                    ..default()
+                }
+                .into(),
+            ),
+            material: materials.add(StandardMaterial {
+                base_color: Color::WHITE,
+                unlit: true,
+                ..default()
+            }),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        CursorMarker,
+    ));
+}
+
+fn stick_with_dead_zone(stick: Vec2) -> Vec2 {
+    let length = stick.length();
+    if length < STICK_DEAD_ZONE {
+        return Vec2::ZERO;
+    }
+    // rescale so movement starts from zero at the edge of the dead zone
+    stick / length * ((length - STICK_DEAD_ZONE) / (1.0 - STICK_DEAD_ZONE)).min(1.0)
+}
+
+// Screen right and screen up as directions on the ground, in (x, z).
+fn ground_axes(camera: Option<&GlobalTransform>) -> (Vec2, Vec2) {
+    let Some(camera) = camera else {
+        return (Vec2::X, Vec2::NEG_Y);
+    };
+    let right = camera.right().xz().normalize_or_zero();
+    let up = camera.forward().xz().normalize_or_zero();
+    (right, up)
+}
+
+fn move_cursor_with_gamepad(
+    gamepads: Res<Gamepads>,
+    axes: Res<Axis<GamepadAxis>>,
+    cameras: Query<&GlobalTransform, With<Camera3d>>,
+    time: Res<Time>,
+    mut cursor: ResMut<GamepadCursor>,
+    mut mouse_pos: ResMut<MousePos>,
+) {
+    // whichever connected pad is pushed furthest wins
+    let stick = gamepads
+        .iter()
+        .map(|gamepad| {
+            let axis = |axis_type| {
+                axes.get(GamepadAxis::new(gamepad, axis_type))
+                    .unwrap_or(0.0)
+            };
+            Vec2::new(
+                axis(GamepadAxisType::LeftStickX),
+                axis(GamepadAxisType::LeftStickY),
+            )
+        })
+        .max_by(|a, b| a.length_squared().total_cmp(&b.length_squared()))
+        .unwrap_or(Vec2::ZERO);
+    let stick = stick_with_dead_zone(stick);
+    if stick == Vec2::ZERO {
+        cursor.held_secs = 0.0;
+        return;
+    }
+    let dt = time.delta_seconds();
+    cursor.held_secs += dt;
+    let ramp = (cursor.held_secs / CURSOR_ACCELERATION_SECS).min(1.0);
+    let speed = CURSOR_BASE_SPEED + (CURSOR_MAX_SPEED - CURSOR_BASE_SPEED) * ramp;
+    let (right, up) = ground_axes(cameras.iter().next());
+    let delta = (right * stick.x + up * stick.y) * speed * dt;
+    let position =
+        (mouse_pos.0.xz() + delta).clamp(Vec2::splat(-CURSOR_BOUNDS), Vec2::splat(CURSOR_BOUNDS));
+    mouse_pos.0 = Vec3::new(position.x, mouse_pos.0.y, position.y);
+}
+
+fn update_cursor_marker(
+    gamepads: Res<Gamepads>,
+    mouse_pos: Res<MousePos>,
+    state: Res<State<GameState>>,
+    mut markers: Query<(&mut Transform, &mut Visibility), With<CursorMarker>>,
+) {
+    let visible = *state.get() == GameState::InGame && gamepads.iter().next().is_some();
+    for (mut transform, mut visibility) in markers.iter_mut() {
+        transform.translation = Vec3::new(mouse_pos.0.x, 0.05, mouse_pos.0.z);
+        *visibility = if visible {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::input::gamepad::{
+        GamepadAxisChangedEvent, GamepadConnection, GamepadConnectionEvent, GamepadEvent,
+        GamepadInfo,
+    };
+    use bevy::input::InputPlugin;
+    use bevy::time::TimeUpdateStrategy;
+    use std::time::Duration;
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, InputPlugin))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(
+                16,
+            )))
+            .add_state::<GameState>()
+            .insert_resource(MousePos::default())
+            .init_resource::<GamepadCursor>()
+            .add_systems(
+                Update,
+                move_cursor_with_gamepad
+                    .run_if(state_exists_and_equals(GameState::InGame).and_then(live_input)),
+            );
+        app.world
+            .resource_mut::<NextState<GameState>>()
+            .set(GameState::InGame);
+        // the same camera the game spawns in `setup`
+        app.world.spawn((
+            Camera3d::default(),
+            GlobalTransform::from(
+                Transform::from_xyz(5.0, 5.0, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
+            ),
+        ));
+        app.world
+            .send_event(GamepadEvent::Connection(GamepadConnectionEvent::new(
+                Gamepad::new(0),
+                GamepadConnection::Connected(GamepadInfo {
+                    name: "test pad".to_string(),
+                }),
+            )));
+        app.update();
+        app
+    }
+
+    fn push_stick(app: &mut App, axis_type: GamepadAxisType, value: f32) {
+        app.world
+            .send_event(GamepadEvent::Axis(GamepadAxisChangedEvent::new(
+                Gamepad::new(0),
+                axis_type,
+                value,
+            )));
+    }
+
+    fn mouse_pos(app: &App) -> Vec2 {
+        app.world.resource::<MousePos>().0.xz()
+    }
+
+    #[test]
+    fn cursor_follows_stick_across_the_screen() {
+        let mut app = app();
+        push_stick(&mut app, GamepadAxisType::LeftStickX, 1.0);
+        for _ in 0..30 {
+            app.update();
+        }
+        let moved = mouse_pos(&app);
+        // screen right for the isometric camera is +x, -z on the ground
+        let screen_right = Vec2::new(1.0, -1.0).normalize();
+        assert!(moved.dot(screen_right) > 0.5, "cursor at {moved}");
+        assert!(
+            moved.perp_dot(screen_right).abs() < 0.01,
+            "cursor at {moved}"
+        );
+
+        push_stick(&mut app, GamepadAxisType::LeftStickX, 0.0);
+        push_stick(&mut app, GamepadAxisType::LeftStickY, 1.0);
+        let before = mouse_pos(&app);
+        for _ in 0..30 {
+            app.update();
+        }
+        // pushing up moves the cursor away from the camera
+        let screen_up = Vec2::new(-1.0, -1.0).normalize();
+        assert!((mouse_pos(&app) - before).dot(screen_up) > 0.5);
+    }
+
+    #[test]
+    fn cursor_accelerates_while_held() {
+        let mut app = app();
+        push_stick(&mut app, GamepadAxisType::LeftStickX, 1.0);
+        app.update();
+        let first_step = mouse_pos(&app).length();
+        for _ in 0..60 {
+            app.update();
+        }
+        let before = mouse_pos(&app);
+        app.update();
+        let late_step = mouse_pos(&app).distance(before);
+        assert!(late_step > first_step * 2.0);
+    }
+
+    #[test]
+    fn small_stick_drift_is_ignored() {
+        let mut app = app();
+        push_stick(&mut app, GamepadAxisType::LeftStickX, 0.1);
+        push_stick(&mut app, GamepadAxisType::LeftStickY, -0.05);
+        for _ in 0..30 {
+            app.update();
+        }
+        assert_eq!(mouse_pos(&app), Vec2::ZERO);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::difficulty::{Difficulty, DifficultyPreset, DifficultyPresets, OvertimeScaling};
+use crate::main_game::replay::TickInput;
+use crate::main_game::tower::TimeSinceGameStart;
+use crate::main_game::TickSet;
+use crate::upgrade::Stats;
+use crate::GameStateChange;
+use bevy::prelude::*;
+
+pub struct TimeGoalPlugin;
+
+impl Plugin for TimeGoalPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TimeGoal::new(&DifficultyPreset::default()));
+        app.add_event::<TimeGoalReached>();
+        app.add_event::<ChooseOvertime>();
+        app.add_systems(Update, set_goal_on_game_start);
+        app.add_systems(
+            FixedUpdate,
+            (check_time_goal, continue_into_overtime).in_set(TickSet::Gameplay),
+        );
+    }
+}
+
+#[derive(Event)]
+pub struct TimeGoalReached(pub Difficulty);
+
+// Handed to the next tick through `TickInput`, so a replay makes the same choice.
+#[derive(Event)]
+pub struct ChooseOvertime;
+
+// The run's time goal, taken from the difficulty it started on.
+#[derive(Resource)]
+pub struct TimeGoal {
+    pub secs: f32,
+    // gold conversion multiplier earned by reaching the goal
+    pub bonus: f32,
+    pub overtime: OvertimeScaling,
+    pub reached: bool,
+    // the player chose overtime rather than cashing out
+    pub continued: bool,
+}
+
+impl TimeGoal {
+    fn new(preset: &DifficultyPreset) -> Self {
+        Self {
+            secs: preset.time_goal_minutes * 60.0,
+            bonus: preset.goal_gold_multiplier,
+            overtime: preset.overtime.clone(),
+            reached: false,
+            continued: false,
+        }
+    }
+
+    pub fn gold_multiplier(&self) -> f32 {
+        if self.reached {
+            self.bonus
+        } else {
+            1.0
+        }
+    }
+
+    // Minutes of overtime `secs` into the run, zero before the goal is reached.
+    pub fn overtime_minutes(&self, secs: f32) -> f32 {
+        if self.reached {
+            (secs - self.secs).max(0.0) / 60.0
+        } else {
+            0.0
+        }
+    }
+
+    pub fn health_scale(&self, secs: f32) -> f32 {
+        1.0 + self.overtime.health_per_minute * self.overtime_minutes(secs)
+    }
+
+    pub fn speed_scale(&self, secs: f32) -> f32 {
+        1.0 + self.overtime.speed_per_minute * self.overtime_minutes(secs)
+    }
+}
+
+fn set_goal_on_game_start(
+    mut event_reader: EventReader<GameStateChange>,
+    mut time_goal: ResMut<TimeGoal>,
+    stats: Stats,
+    presets: Res<DifficultyPresets>,
+) {
+    for ev in event_reader.read() {
+        if let GameStateChange::MainGame = ev {
+            *time_goal = TimeGoal::new(&presets.get(stats.difficulty()));
+        }
+    }
+}
+
+fn check_time_goal(
+    mut time_goal: ResMut<TimeGoal>,
+    time_since_game_start: Res<TimeSinceGameStart>,
+    stats: Stats,
+    mut reached_events: EventWriter<TimeGoalReached>,
+) {
+    if !time_goal.reached && time_since_game_start.0 >= time_goal.secs {
+        time_goal.reached = true;
+        reached_events.send(TimeGoalReached(stats.difficulty()));
+    }
+}
+
+fn continue_into_overtime(tick_input: Res<TickInput>, mut time_goal: ResMut<TimeGoal>) {
+    if tick_input.overtime && time_goal.reached {
+        time_goal.continued = true;
+    }
+}
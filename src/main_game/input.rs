@@ -0,0 +1,290 @@
+use crate::GameState;
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::*;
+use leafwing_input_manager::user_input::{Modifier, UserInput};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub struct PlayerInputPlugin;
+
+impl Plugin for PlayerInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(InputManagerPlugin::<PlayerAction>::default());
+        app.init_resource::<ActionState<PlayerAction>>();
+        app.init_resource::<InputMap<PlayerAction>>();
+        app.insert_resource(Bindings::default());
+        app.insert_resource(Rebinding(None));
+        app.add_systems(Update, apply_bindings);
+        app.add_systems(
+            Update,
+            capture_rebinding.run_if(
+                state_exists_and_equals(GameState::Staging)
+                    .or_else(state_exists_and_equals(GameState::Paused)),
+            ),
+        );
+    }
+}
+
+#[derive(
+    Actionlike,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Clone,
+    Copy,
+    Debug,
+    Reflect,
+    Serialize,
+    Deserialize,
+)]
+pub enum PlayerAction {
+    PlaceTower,
+    UpgradeTower,
+    SellTower,
+    MoveTower,
+    Duplicate,
+    Pause,
+    StartGame,
+}
+
+impl PlayerAction {
+    pub const ALL: [PlayerAction; 7] = [
+        PlayerAction::PlaceTower,
+        PlayerAction::UpgradeTower,
+        PlayerAction::SellTower,
+        PlayerAction::MoveTower,
+        PlayerAction::Duplicate,
+        PlayerAction::Pause,
+        PlayerAction::StartGame,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            PlayerAction::PlaceTower => "place tower",
+            PlayerAction::UpgradeTower => "upgrade tower (hold)",
+            PlayerAction::SellTower => "sell tower",
+            PlayerAction::MoveTower => "pick up tower",
+            PlayerAction::Duplicate => "duplicate enemies",
+            PlayerAction::Pause => "pause",
+            PlayerAction::StartGame => "start game",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    ShiftMouse(MouseButton),
+    Gamepad(GamepadButtonType),
+}
+
+impl Binding {
+    pub fn device(self) -> Device {
+        match self {
+            Binding::Gamepad(_) => Device::Gamepad,
+            _ => Device::KeyboardMouse,
+        }
+    }
+
+    pub fn label(self) -> String {
+        match self {
+            Binding::Key(key) => format!("{key:?}"),
+            Binding::Mouse(button) => format!("{button:?} click"),
+            Binding::ShiftMouse(button) => format!("shift {button:?} click"),
+            Binding::Gamepad(button) => format!("{button:?}"),
+        }
+    }
+
+    fn user_input(self) -> UserInput {
+        match self {
+            Binding::Key(key) => key.into(),
+            Binding::Mouse(button) => button.into(),
+            Binding::ShiftMouse(button) => UserInput::modified(Modifier::Shift, button),
+            Binding::Gamepad(button) => button.into(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Device {
+    KeyboardMouse,
+    Gamepad,
+}
+
+// The persisted form of the input map, at most one binding per action and device.
+#[derive(Resource, Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Bindings(pub BTreeMap<PlayerAction, Vec<Binding>>);
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self(BTreeMap::from([
+            (
+                PlayerAction::PlaceTower,
+                vec![
+                    Binding::Mouse(MouseButton::Left),
+                    Binding::Gamepad(GamepadButtonType::South),
+                ],
+            ),
+            (
+                PlayerAction::UpgradeTower,
+                vec![
+                    Binding::Mouse(MouseButton::Right),
+                    Binding::Gamepad(GamepadButtonType::East),
+                ],
+            ),
+            (
+                PlayerAction::SellTower,
+                vec![
+                    Binding::Key(KeyCode::X),
+                    Binding::Gamepad(GamepadButtonType::West),
+                ],
+            ),
+            (
+                PlayerAction::MoveTower,
+                vec![
+                    Binding::Key(KeyCode::M),
+                    Binding::Gamepad(GamepadButtonType::LeftTrigger),
+                ],
+            ),
+            (
+                PlayerAction::Duplicate,
+                vec![
+                    Binding::ShiftMouse(MouseButton::Left),
+                    Binding::Gamepad(GamepadButtonType::North),
+                ],
+            ),
+            (
+                PlayerAction::Pause,
+                vec![
+                    Binding::Key(KeyCode::Escape),
+                    Binding::Gamepad(GamepadButtonType::Start),
+                ],
+            ),
+            (
+                PlayerAction::StartGame,
+                vec![
+                    Binding::Key(KeyCode::Return),
+                    Binding::Gamepad(GamepadButtonType::Start),
+                ],
+            ),
+        ]))
+    }
+}
+
+impl Bindings {
+    pub fn get(&self, action: PlayerAction, device: Device) -> Option<Binding> {
+        self.0
+            .get(&action)?
+            .iter()
+            .copied()
+            .find(|binding| binding.device() == device)
+    }
+
+    // Every binding of the action, for telling the player what to press.
+    pub fn label(&self, action: PlayerAction) -> String {
+        let labels: Vec<String> = self
+            .0
+            .get(&action)
+            .into_iter()
+            .flatten()
+            .map(|binding| binding.label())
+            .collect();
+        if labels.is_empty() {
+            "unbound".to_string()
+        } else {
+            labels.join(" or ")
+        }
+    }
+
+    pub fn set(&mut self, action: PlayerAction, binding: Binding) {
+        let bindings = self.0.entry(action).or_default();
+        bindings.retain(|b| b.device() != binding.device());
+        bindings.push(binding);
+    }
+
+    pub fn input_map(&self) -> InputMap<PlayerAction> {
+        let mut input_map = InputMap::default();
+        for (action, bindings) in self.0.iter() {
+            for binding in bindings {
+                input_map.insert(binding.user_input(), *action);
+            }
+        }
+        input_map
+    }
+}
+
+// The action and device waiting for the next button press on the rebinding screen.
+#[derive(Resource)]
+pub struct Rebinding(pub Option<(PlayerAction, Device)>);
+
+fn apply_bindings(bindings: Res<Bindings>, mut input_map: ResMut<InputMap<PlayerAction>>) {
+    if bindings.is_changed() {
+        *input_map = bindings.input_map();
+    }
+}
+
+pub(crate) fn capture_rebinding(
+    mut rebinding: ResMut<Rebinding>,
+    mut bindings: ResMut<Bindings>,
+    keys: Res<Input<KeyCode>>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+) {
+    let Some((action, device)) = rebinding.0 else {
+        return;
+    };
+    let binding = match device {
+        Device::KeyboardMouse => {
+            if keys.just_pressed(KeyCode::Escape) && action != PlayerAction::Pause {
+                rebinding.0 = None;
+                return;
+            }
+            let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+            // shift on its own only modifies a mouse button
+            let key = keys
+                .get_just_pressed()
+                .find(|key| !matches!(key, KeyCode::ShiftLeft | KeyCode::ShiftRight));
+            match (key, mouse_buttons.get_just_pressed().next()) {
+                (Some(key), _) => Binding::Key(*key),
+                (None, Some(button)) if shift => Binding::ShiftMouse(*button),
+                (None, Some(button)) => Binding::Mouse(*button),
+                (None, None) => return,
+            }
+        }
+        Device::Gamepad => match gamepad_buttons.get_just_pressed().next() {
+            Some(button) => Binding::Gamepad(button.button_type),
+            None => return,
+        },
+    };
+    bindings.set(action, binding);
+    rebinding.0 = None;
+}
+
+// Takes the `ResMut` so that only an actual rebind marks the bindings as changed.
+pub fn bindings_ui(ui: &mut egui::Ui, bindings: &mut ResMut<Bindings>, rebinding: &mut Rebinding) {
+    egui::Grid::new("bindings").show(ui, |ui| {
+        for action in PlayerAction::ALL {
+            ui.label(action.label());
+            for device in [Device::KeyboardMouse, Device::Gamepad] {
+                let text = if rebinding.0 == Some((action, device)) {
+                    "press a button...".to_string()
+                } else {
+                    bindings
+                        .get(action, device)
+                        .map_or("unbound".to_string(), Binding::label)
+                };
+                if ui.button(text).clicked() {
+                    rebinding.0 = Some((action, device));
+                }
+            }
+            ui.end_row();
+        }
+    });
+    if ui.button("reset to defaults").clicked() {
+        **bindings = Bindings::default();
+        rebinding.0 = None;
+    }
+}
@@ -1,43 +1,146 @@
 mod bullet;
+mod duplicate;
 mod enemy;
-mod mouse;
-mod tower;
+mod game_assets;
+pub mod game_over;
+mod gamepad;
+pub mod goal;
+pub mod input;
+pub mod mouse;
+pub mod pause;
+pub mod placement;
+mod pool;
+pub mod replay;
+pub mod rng;
+pub mod selection;
+pub mod tower;
+pub mod wave;
 
 use crate::main_game::bullet::BulletPlugin;
+use crate::main_game::duplicate::{DuplicateCooldown, DuplicatePlugin};
 use crate::main_game::enemy::{Enemy, EnemyPlugin};
-use crate::main_game::mouse::MousePlugin;
-use crate::main_game::tower::{TimeSinceGameStart, TowerPlugin};
-use crate::{GameState, GameStateChange, Gold, GoldConversionRateLvl};
+use crate::main_game::game_assets::GameAssetsPlugin;
+use crate::main_game::game_over::{GameOverPlugin, RunSummary, TowerSummary};
+use crate::main_game::gamepad::GamepadCursorPlugin;
+use crate::main_game::goal::{ChooseOvertime, TimeGoal, TimeGoalPlugin};
+use crate::main_game::input::{Bindings, PlayerAction, PlayerInputPlugin};
+use crate::main_game::mouse::{MousePlugin, PlayerHealth};
+use crate::main_game::pause::PausePlugin;
+use crate::main_game::placement::PlacementPlugin;
+use crate::main_game::pool::{EntityPool, PoolPlugin};
+use crate::main_game::replay::{replay_has_input, ReplayMode, ReplayPlugin};
+use crate::main_game::rng::{reseed_on_game_start, FixedSeed, GameRng};
+use crate::main_game::selection::{
+    move_cost, sell_refund, CarriedTower, SelectedTower, TowerCommand, TowerSelectionPlugin,
+};
+use crate::main_game::tower::{
+    RunTowers, SelectedTowerType, TargetingPolicy, TimeSinceGameStart, TowerId, TowerKind,
+    TowerLevel, TowerPlugin, TowerTypes,
+};
+use crate::main_game::wave::{WaveDirector, WavePlugin};
+use crate::upgrade::{Stat, Stats};
+use crate::{Diamonds, GameState, GameStateChange, Gold, LifetimeStats};
 use bevy::app::{App, PluginGroupBuilder};
+use bevy::ecs::schedule::ExecutorKind;
 use bevy::prelude::*;
 use bevy_egui::EguiContexts;
+use bevy_xpbd_3d::prelude::{PhysicsLayer, PhysicsSet};
+use std::time::Duration;
 
 pub struct MainGamePlugin;
 
+pub struct MainGameUiPlugin;
+
 pub struct MainGamePlugins;
 
 impl PluginGroup for MainGamePlugins {
     fn build(self) -> PluginGroupBuilder {
         PluginGroupBuilder::start::<MainGamePlugins>()
             .add(MainGamePlugin)
+            .add(MainGameUiPlugin)
             .add(TowerPlugin)
+            .add(PlacementPlugin)
+            .add(TowerSelectionPlugin)
             .add(MousePlugin)
             .add(EnemyPlugin)
             .add(BulletPlugin)
+            .add(WavePlugin)
+            .add(PlayerInputPlugin)
+            .add(GamepadCursorPlugin)
+            .add(DuplicatePlugin)
+            .add(GameAssetsPlugin)
+            .add(PoolPlugin)
+            .add(PausePlugin)
+            .add(GameOverPlugin)
+            .add(TimeGoalPlugin)
+            .add(ReplayPlugin)
     }
 }
 
+// Gameplay advances in fixed ticks of this length, however the frames fall.
+pub const TICK: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+// Every `FixedUpdate` tick of a run goes through these in order, with the physics step between
+// `Gameplay` and `Collisions`.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TickSet {
+    // takes the tick's input and gets the pool and grids ready
+    Input,
+    Gameplay,
+    // reacts to the collisions the physics step just found
+    Collisions,
+}
+
 impl Plugin for MainGamePlugin {
     fn build(&self, app: &mut App) {
+        app.insert_resource(Time::<Fixed>::from_duration(TICK));
+        app.configure_sets(
+            FixedUpdate,
+            (TickSet::Input, TickSet::Gameplay)
+                .chain()
+                .before(PhysicsSet::Prepare),
+        );
+        app.configure_sets(FixedUpdate, TickSet::Collisions.after(PhysicsSet::Sync));
+        app.configure_sets(
+            FixedUpdate,
+            TickSet::Input.run_if(state_exists_and_equals(GameState::InGame)),
+        );
+        // a finished replay stops the run where the recording did
+        for set in [TickSet::Gameplay, TickSet::Collisions] {
+            app.configure_sets(
+                FixedUpdate,
+                set.run_if(state_exists_and_equals(GameState::InGame).and_then(replay_has_input)),
+            );
+        }
+        // the multi-threaded executor may order systems sharing the rng differently between runs
+        app.edit_schedule(FixedUpdate, |schedule| {
+            schedule.set_executor_kind(ExecutorKind::SingleThreaded);
+        });
         app.add_systems(
             Update,
-            on_die.run_if(state_exists_and_equals(GameState::InGame)),
+            // a run can also be abandoned from the pause menu
+            on_die.run_if(
+                state_exists_and_equals(GameState::InGame)
+                    .or_else(state_exists_and_equals(GameState::Paused)),
+            ),
         );
         app.insert_resource(Score(0));
         app.insert_resource(PlacedTowers(0));
+        app.insert_resource(RunKills(0));
+        app.init_resource::<RunGold>();
+        app.init_resource::<RunSummary>();
+        app.insert_resource(GameRng::new(0));
+        app.insert_resource(FixedSeed::default());
+        app.add_event::<RunEnded>();
+        app.add_systems(Update, reseed_on_game_start);
+    }
+}
+
+impl Plugin for MainGameUiPlugin {
+    fn build(&self, app: &mut App) {
         app.add_systems(
             Update,
-            ui.run_if(state_exists_and_equals(GameState::InGame)),
+            (ui, tower_ui, time_goal_ui).run_if(state_exists_and_equals(GameState::InGame)),
         );
     }
 }
@@ -48,21 +151,168 @@ fn ui(
     score: ResMut<Score>,
     placed_towers: Res<PlacedTowers>,
     time_since_game_start: Res<TimeSinceGameStart>,
+    wave_director: Res<WaveDirector>,
+    player_health: Res<PlayerHealth>,
+    duplicate_cooldown: Res<DuplicateCooldown>,
+    time_goal: Res<TimeGoal>,
+    bindings: Res<Bindings>,
+    stats: Stats,
 ) {
     let ctx = contexts.ctx_mut();
     egui::SidePanel::left("my_left")
         .resizable(false)
         .show(ctx, |ui| {
-            if ui.button("end game").clicked() {
+            let end_label = if time_goal.reached {
+                "cash out"
+            } else {
+                "end game"
+            };
+            if ui.button(end_label).clicked() {
                 event_writer.send(GameStateChange::Staging);
             }
+            let fill = if player_health.invulnerable_secs > 0.0 {
+                egui::Color32::from_rgb(230, 120, 120)
+            } else {
+                egui::Color32::from_rgb(200, 40, 40)
+            };
+            ui.add(
+                egui::ProgressBar::new(player_health.current / player_health.max)
+                    .fill(fill)
+                    .text(format!(
+                        "health {}/{}",
+                        player_health.current, player_health.max
+                    )),
+            );
             ui.label(format!("score: {}", score.0));
             ui.label(format!(
                 "avaliable towers: {}",
-                calculate_available_towers(*score, *placed_towers)
+                calculate_available_towers(*score, *placed_towers, &stats)
+            ));
+            ui.label(format!(
+                "minutes elapsed: {}",
+                time_since_game_start.0 / 60.0
             ));
-            ui.label(format!("minutes elapsed: {}", time_since_game_start.0 / 60.0));
-            ui.label(format!("time goal: {}", 6.666))
+            if time_goal.reached {
+                ui.label(format!(
+                    "overtime: {:.1} minutes, gold x{}",
+                    time_goal.overtime_minutes(time_since_game_start.0),
+                    time_goal.bonus
+                ));
+            } else {
+                ui.label(format!("time goal: {:.3}", time_goal.secs / 60.0));
+            }
+            ui.label(format!("wave: {}", wave_director.wave));
+            if duplicate_cooldown.0 > 0.0 {
+                ui.label(format!("duplicate in {:.1}s", duplicate_cooldown.0));
+            } else {
+                // the input map is rebuilt from these, so this follows a rebind
+                ui.label(format!(
+                    "duplicate ready ({})",
+                    bindings.label(PlayerAction::Duplicate)
+                ));
+            }
+        });
+}
+
+fn tower_ui(
+    mut contexts: EguiContexts,
+    score: Res<Score>,
+    tower_types: Res<TowerTypes>,
+    mut selected_type: ResMut<SelectedTowerType>,
+    selected: Res<SelectedTower>,
+    carried: Res<CarriedTower>,
+    towers: Query<(&TowerId, &TowerLevel, &TowerKind, &TargetingPolicy)>,
+    mut tower_commands: EventWriter<TowerCommand>,
+) {
+    let name = |kind: &str| {
+        tower_types
+            .get(kind)
+            .map_or(kind.to_string(), |t| t.name.clone())
+    };
+    let ctx = contexts.ctx_mut();
+    egui::SidePanel::right("towers")
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.label("place:");
+            for tower_type in tower_types.towers.iter() {
+                let is_selected = selected_type.0 == tower_type.id;
+                if ui.selectable_label(is_selected, &tower_type.name).clicked() && !is_selected {
+                    selected_type.0 = tower_type.id.clone();
+                }
+            }
+            ui.separator();
+            if let Some(tower) = &carried.0 {
+                ui.label(format!(
+                    "carrying a level {} {} tower, click to put it down",
+                    tower.level,
+                    name(&tower.kind)
+                ));
+            } else if let Some((&tower, level, kind, policy)) =
+                selected.0.and_then(|tower| towers.get(tower).ok())
+            {
+                let level = level.0;
+                ui.label(format!("selected {} tower level: {}", name(&kind.0), level));
+                let mut choice = *policy;
+                egui::ComboBox::from_label("target")
+                    .selected_text(choice.label())
+                    .show_ui(ui, |ui| {
+                        for option in TargetingPolicy::ALL {
+                            ui.selectable_value(&mut choice, option, option.label());
+                        }
+                    });
+                if choice != *policy {
+                    tower_commands.send(TowerCommand::Target(tower, choice));
+                }
+                if let Some(tower_type) = tower_types.get(&kind.0) {
+                    ui.label(format!(
+                        "targets per volley: {}",
+                        tower_type.max_targets(level)
+                    ));
+                }
+                if ui
+                    .button(format!("sell (+{} gold)", sell_refund(level)))
+                    .clicked()
+                {
+                    tower_commands.send(TowerCommand::Sell(tower));
+                }
+                let cost = move_cost(level);
+                if score.0 >= cost {
+                    if ui.button(format!("move (-{} score)", cost)).clicked() {
+                        tower_commands.send(TowerCommand::PickUp(tower));
+                    }
+                } else {
+                    ui.label(format!("moving needs {} score", cost));
+                }
+            }
+        });
+}
+
+// Asks once whether to end the run with the goal's bonus or play on into overtime.
+fn time_goal_ui(
+    mut contexts: EguiContexts,
+    time_goal: Res<TimeGoal>,
+    mut event_writer: EventWriter<GameStateChange>,
+    mut overtime_choices: EventWriter<ChooseOvertime>,
+) {
+    if !time_goal.reached || time_goal.continued {
+        return;
+    }
+    let ctx = contexts.ctx_mut();
+    egui::Window::new("time goal reached")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_TOP, [0.0, 10.0])
+        .show(ctx, |ui| {
+            ui.label(format!("score converts to gold at x{}", time_goal.bonus));
+            ui.horizontal(|ui| {
+                if ui.button("cash out").clicked() {
+                    event_writer.send(GameStateChange::Staging);
+                }
+                if ui.button("overtime").clicked() {
+                    overtime_choices.send(ChooseOvertime);
+                }
+            });
+            ui.label("enemies get stronger every minute of overtime");
         });
 }
 
@@ -72,31 +322,136 @@ pub struct Score(pub u32);
 #[derive(Resource, Clone, Copy)]
 pub struct PlacedTowers(pub u32);
 
-pub fn calculate_available_towers(score: Score, placed_towers: PlacedTowers) -> u32 {
-    (1 + (score.0 / 50)) - placed_towers.0
+#[derive(Resource)]
+pub struct RunKills(pub u32);
+
+// Gold picked up during the run, only paid out when it ends so that a replay earns nothing.
+#[derive(Resource, Default)]
+pub struct RunGold {
+    pub bounties: f32,
+    pub mines: f32,
+    pub refunds: f32,
+}
+
+impl RunGold {
+    pub fn total(&self) -> f32 {
+        self.bounties + self.mines + self.refunds
+    }
+}
+
+const GOLD_PER_DIAMOND: f32 = 1000.0;
+const KILLS_PER_DIAMOND: u64 = 1000;
+
+pub fn calculate_available_towers(score: Score, placed_towers: PlacedTowers, stats: &Stats) -> u32 {
+    let modifiers = stats.modifiers();
+    let starting_towers = stats.get(Stat::StartingTowers).max(0.0) as u32;
+    let towers = 1 + starting_towers + score.0 / modifiers.score_per_tower;
+    towers
+        .min(modifiers.max_towers.unwrap_or(u32::MAX))
+        .saturating_sub(placed_towers.0)
 }
 
 #[derive(Component)]
 pub struct Speed(f32);
 
+#[derive(Event)]
+pub struct RunEnded {
+    pub score: u32,
+    pub survival_secs: f32,
+    pub towers_placed: u32,
+}
+
 fn on_die(
     mut event_reader: EventReader<GameStateChange>,
     mut commands: Commands,
+    mut pool: ResMut<EntityPool>,
     enemies: Query<Entity, With<Enemy>>,
+    run_towers: Res<RunTowers>,
     mut score: ResMut<Score>,
     mut placed_towers: ResMut<PlacedTowers>,
-    mut gold: ResMut<Gold>,
-    gold_conversion_rate_lvl: Res<GoldConversionRateLvl>,
+    (mut gold, mut diamonds): (ResMut<Gold>, ResMut<Diamonds>),
+    (mut run_kills, mut run_gold): (ResMut<RunKills>, ResMut<RunGold>),
+    stats: Stats,
+    mut lifetime_stats: ResMut<LifetimeStats>,
+    (time_since_game_start, time_goal): (Res<TimeSinceGameStart>, Res<TimeGoal>),
+    replay_mode: Res<ReplayMode>,
+    mut run_ended: EventWriter<RunEnded>,
+    mut summary: ResMut<RunSummary>,
 ) {
     for ev in event_reader.read() {
         match ev {
             GameStateChange::Staging => {
                 for enemy in enemies.iter() {
-                    commands.entity(enemy).despawn();
+                    pool.recycle_enemy(&mut commands, enemy);
                 }
-                gold.0 += (score.0 as f32) * ((gold_conversion_rate_lvl.0 as f32).log(1.5) / 5.0);
+                run_ended.send(RunEnded {
+                    score: score.0,
+                    survival_secs: time_since_game_start.0,
+                    towers_placed: run_towers.0.len() as u32,
+                });
+                let conversion_rate = stats.get(Stat::GoldConversionRate);
+                let goal_multiplier = time_goal.gold_multiplier();
+                let difficulty_multiplier = stats.modifiers().gold_multiplier;
+                let from_score =
+                    (score.0 as f32) * conversion_rate * goal_multiplier * difficulty_multiplier;
+                let earned = from_score + run_gold.total();
+                let new_best = score.0 > lifetime_stats.best_score && !replay_mode.is_playing();
+                let mut diamonds_from_kills = 0;
+                let mut converted = 0.0;
+                // watching a replay pays nothing out
+                if !replay_mode.is_playing() {
+                    gold.0 += earned;
+                    lifetime_stats.runs += 1;
+                    lifetime_stats.total_score += score.0 as u64;
+                    lifetime_stats.best_score = lifetime_stats.best_score.max(score.0);
+                    lifetime_stats.total_gold_earned += earned;
+                    lifetime_stats.longest_run_secs =
+                        lifetime_stats.longest_run_secs.max(time_since_game_start.0);
+                    // one diamond for every thousand kills across all runs
+                    let kills_before = lifetime_stats.kills;
+                    lifetime_stats.kills += run_kills.0 as u64;
+                    diamonds_from_kills = (lifetime_stats.kills / KILLS_PER_DIAMOND
+                        - kills_before / KILLS_PER_DIAMOND)
+                        as u32;
+                    diamonds.0 += diamonds_from_kills;
+                    // leftover gold converts at 1000:1, the remainder stays as gold
+                    converted = (gold.0 / GOLD_PER_DIAMOND).floor();
+                    diamonds.0 += converted as u32;
+                    gold.0 -= converted * GOLD_PER_DIAMOND;
+                }
+                let mut tower_summaries: Vec<TowerSummary> =
+                    run_towers.0.values().cloned().collect();
+                tower_summaries.sort_by(|a, b| b.kills.cmp(&a.kills));
+                *summary = RunSummary {
+                    score: score.0,
+                    survival_secs: time_since_game_start.0,
+                    time_goal_secs: time_goal.secs,
+                    goal_reached: time_goal.reached,
+                    towers_placed: run_towers.0.len() as u32,
+                    highest_tower_level: tower_summaries
+                        .iter()
+                        .map(|tower| tower.level)
+                        .max()
+                        .unwrap_or(0),
+                    towers: tower_summaries,
+                    conversion_rate,
+                    goal_multiplier,
+                    difficulty_multiplier,
+                    gold_from_score: from_score,
+                    gold_from_bounties: run_gold.bounties,
+                    gold_from_mines: run_gold.mines,
+                    gold_from_refunds: run_gold.refunds,
+                    gold_earned: earned,
+                    diamonds_from_gold: converted as u32,
+                    diamonds_from_kills,
+                    gold_left: gold.0,
+                    new_best,
+                };
+                run_kills.0 = 0;
+                *run_gold = RunGold::default();
                 score.0 = 0;
                 placed_towers.0 = 0;
+                break;
             }
             GameStateChange::MainGame => {}
         }
@@ -105,3 +460,11 @@ fn on_die(
 
 #[derive(Component)]
 pub struct Health(f32);
+
+// Which physics bodies can touch, bullets only ever collide with enemies.
+#[derive(PhysicsLayer)]
+pub enum GameLayer {
+    Ground,
+    Enemy,
+    Bullet,
+}
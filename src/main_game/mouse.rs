@@ -1,8 +1,12 @@
-use crate::main_game::enemy::Enemy;
+use crate::main_game::enemy::EnemyGrid;
+use crate::main_game::pool::EntityPool;
+use crate::main_game::replay::live_input;
+use crate::main_game::TickSet;
+use crate::upgrade::{Stat, Stats};
 use crate::{GameState, GameStateChange};
 use bevy::prelude::*;
-use bevy::window::PrimaryWindow;
-use bevy_mod_picking::backends::raycast::bevy_mod_raycast::prelude::Ray3d;
+use bevy::render::camera::CameraProjection;
+use bevy::window::CursorMoved;
 use bevy_mod_picking::prelude::PointerLocation;
 
 pub struct MousePlugin;
@@ -12,50 +16,254 @@ impl Plugin for MousePlugin {
         app.insert_resource(MousePos::default());
         app.add_systems(
             Update,
-            set_mouse_pos.run_if(state_exists_and_equals(GameState::InGame)),
-        );
-        app.add_systems(
-            Update,
-            kill_player.run_if(state_exists_and_equals(GameState::InGame)),
+            set_mouse_pos.run_if(state_exists_and_equals(GameState::InGame).and_then(live_input)),
         );
+        app.add_systems(FixedUpdate, hurt_player.in_set(TickSet::Gameplay));
+        app.add_systems(Update, reset_player_health);
+        app.insert_resource(PlayerHealth::default());
+        app.add_event::<PlayerDamaged>();
     }
 }
 
 #[derive(Resource, Default)]
 pub struct MousePos(pub Vec3);
 
+const TOUCH_DISTANCE: f32 = 0.03;
+const INVULNERABLE_SECS: f32 = 1.0;
+
+// Never regenerates during a run, only the max can be raised in staging.
+#[derive(Resource, Default)]
+pub struct PlayerHealth {
+    pub current: f32,
+    pub max: f32,
+    pub invulnerable_secs: f32,
+}
+
+#[derive(Event)]
+pub struct PlayerDamaged {
+    pub amount: f32,
+    pub remaining: f32,
+}
+
 fn set_mouse_pos(
-    primary_window: Query<&Window, With<PrimaryWindow>>,
     pointer_location: Query<&PointerLocation>,
-    picking_cameras: Query<(&Camera, &GlobalTransform)>,
+    cameras: Query<(&Camera, &GlobalTransform, &Projection), With<Camera3d>>,
     mut mouse_pos: ResMut<MousePos>,
+    mut cursor_moved: EventReader<CursorMoved>,
 ) {
+    // leave the cursor where it is while a gamepad is steering it
+    if cursor_moved.is_empty() {
+        return;
+    }
+    cursor_moved.clear();
     for pointer_loc in pointer_location.iter() {
-        for (camera, transform) in picking_cameras.iter() {
-            let mut viewport_pos = pointer_loc.location().unwrap().position;
-            viewport_pos.y = (viewport_pos.y - primary_window.single().height()) * 3.0;
-            let ray = camera
-                .viewport_to_world(transform, viewport_pos)
-                .map(Ray3d::from)
-                .unwrap();
-            mouse_pos.0 = ray.origin();
+        // the pointer left the window, keep the last position
+        let Some(location) = pointer_loc.location() else {
+            continue;
+        };
+        for (camera, transform, projection) in cameras.iter() {
+            let Some(viewport_size) = camera.logical_viewport_size() else {
+                continue;
+            };
+            if let Some(ground) = viewport_to_ground(
+                location.position,
+                viewport_size,
+                projection.get_projection_matrix(),
+                transform,
+            ) {
+                mouse_pos.0 = ground;
+            }
         }
     }
 }
 
-fn kill_player(
-    enemies: Query<&Transform, With<Enemy>>,
-    mouse: ResMut<MousePos>,
+// Where the ray through a viewport position (in logical pixels, origin top left) meets the
+// y = 0 ground plane, or None if it never does.
+pub fn viewport_to_ground(
+    viewport_pos: Vec2,
+    viewport_size: Vec2,
+    projection: Mat4,
+    camera_transform: &GlobalTransform,
+) -> Option<Vec3> {
+    let ndc = Vec2::new(
+        viewport_pos.x / viewport_size.x * 2.0 - 1.0,
+        1.0 - viewport_pos.y / viewport_size.y * 2.0,
+    );
+    let ndc_to_world = camera_transform.compute_matrix() * projection.inverse();
+    // bevy uses reversed z, so the near plane is at 1
+    let near = ndc_to_world.project_point3(ndc.extend(1.0));
+    let far = ndc_to_world.project_point3(ndc.extend(f32::EPSILON));
+    if near.is_nan() || far.is_nan() {
+        return None;
+    }
+    let ray = Ray {
+        origin: near,
+        direction: (far - near).normalize(),
+    };
+    let distance = ray.intersect_plane(Vec3::ZERO, Vec3::Y)?;
+    let mut ground = ray.get_point(distance);
+    ground.y = 0.0;
+    Some(ground)
+}
+
+fn reset_player_health(
+    mut event_reader: EventReader<GameStateChange>,
+    mut player_health: ResMut<PlayerHealth>,
+    stats: Stats,
+) {
+    for ev in event_reader.read() {
+        if let GameStateChange::MainGame = ev {
+            let max = stats.get(Stat::MaxHealth).round().max(1.0);
+            *player_health = PlayerHealth {
+                current: max,
+                max,
+                invulnerable_secs: 0.0,
+            };
+        }
+    }
+}
+
+fn hurt_player(
+    mut commands: Commands,
+    mut pool: ResMut<EntityPool>,
+    enemy_grid: Res<EnemyGrid>,
+    mouse: Res<MousePos>,
+    time: Res<Time>,
+    mut player_health: ResMut<PlayerHealth>,
+    mut damaged_events: EventWriter<PlayerDamaged>,
     mut event_writer: EventWriter<GameStateChange>,
+    stats: Stats,
 ) {
-    let mut mouse = mouse.0;
-    mouse.y = 0.0;
-    for enemy in enemies.iter() {
-        let mut t = enemy.translation;
-        t.y = 0.0;
-        if mouse.distance(t) < 0.03 {
-            println!("dead");
-            event_writer.send(GameStateChange::Staging)
+    if player_health.invulnerable_secs > 0.0 {
+        player_health.invulnerable_secs -= time.delta_seconds();
+    }
+    let touch_distance = TOUCH_DISTANCE * stats.modifiers().touch_radius;
+    for (enemy, position) in enemy_grid.within(mouse.0, touch_distance) {
+        if mouse.0.xz().distance(position.xz()) >= touch_distance {
+            continue;
+        }
+        // the enemy is used up on contact, even while the player is invulnerable
+        pool.recycle_enemy(&mut commands, enemy);
+        if player_health.invulnerable_secs > 0.0 {
+            continue;
+        }
+        player_health.current -= 1.0;
+        player_health.invulnerable_secs = INVULNERABLE_SECS;
+        damaged_events.send(PlayerDamaged {
+            amount: 1.0,
+            remaining: player_health.current,
+        });
+        if player_health.current <= 0.0 {
+            event_writer.send(GameStateChange::Staging);
+            return;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::camera::ScalingMode;
+
+    // matches the camera spawned in `setup`
+    fn camera(width: f32, height: f32) -> (Mat4, GlobalTransform) {
+        let mut projection = OrthographicProjection {
+            scale: 3.0,
+            scaling_mode: ScalingMode::FixedVertical(2.0),
+            ..default()
+        };
+        projection.update(width, height);
+        let transform = Transform::from_xyz(5.0, 5.0, 5.0).looking_at(Vec3::ZERO, Vec3::Y);
+        (projection.get_projection_matrix(), transform.into())
+    }
+
+    fn ground_to_viewport(
+        ground: Vec3,
+        viewport_size: Vec2,
+        projection: Mat4,
+        camera_transform: &GlobalTransform,
+    ) -> Vec2 {
+        let world_to_ndc = projection * camera_transform.compute_matrix().inverse();
+        let ndc = world_to_ndc.project_point3(ground).truncate();
+        Vec2::new(
+            (ndc.x + 1.0) / 2.0 * viewport_size.x,
+            (1.0 - ndc.y) / 2.0 * viewport_size.y,
+        )
+    }
+
+    #[test]
+    fn viewport_center_hits_the_point_the_camera_looks_at() {
+        let size = Vec2::new(1280.0, 720.0);
+        let (projection, transform) = camera(size.x, size.y);
+        let ground = viewport_to_ground(size / 2.0, size, projection, &transform).unwrap();
+        assert!(ground.distance(Vec3::ZERO) < 1e-4, "{ground}");
+    }
+
+    #[test]
+    fn projection_lands_on_the_ground_plane() {
+        let size = Vec2::new(800.0, 600.0);
+        let (projection, transform) = camera(size.x, size.y);
+        for position in [Vec2::ZERO, Vec2::new(800.0, 0.0), Vec2::new(123.0, 456.0)] {
+            let ground = viewport_to_ground(position, size, projection, &transform).unwrap();
+            assert_eq!(ground.y, 0.0);
+        }
+    }
+
+    #[test]
+    fn projection_round_trips_ground_points() {
+        let size = Vec2::new(1024.0, 768.0);
+        let (projection, transform) = camera(size.x, size.y);
+        for ground in [
+            Vec3::new(1.0, 0.0, -2.0),
+            Vec3::new(-3.5, 0.0, 0.5),
+            Vec3::new(0.2, 0.0, 4.0),
+        ] {
+            let viewport = ground_to_viewport(ground, size, projection, &transform);
+            let back = viewport_to_ground(viewport, size, projection, &transform).unwrap();
+            assert!(back.distance(ground) < 1e-3, "{ground} came back as {back}");
+        }
+    }
+
+    #[test]
+    fn projection_does_not_depend_on_resolution() {
+        let small = Vec2::new(800.0, 600.0);
+        let large = Vec2::new(1600.0, 1200.0);
+        let (small_projection, transform) = camera(small.x, small.y);
+        let (large_projection, _) = camera(large.x, large.y);
+        let a = viewport_to_ground(small * 0.25, small, small_projection, &transform).unwrap();
+        let b = viewport_to_ground(large * 0.25, large, large_projection, &transform).unwrap();
+        assert!(a.distance(b) < 1e-4, "{a} vs {b}");
+    }
+
+    #[test]
+    fn screen_edges_move_along_screen_axes() {
+        let size = Vec2::new(1280.0, 720.0);
+        let (projection, transform) = camera(size.x, size.y);
+        let center = viewport_to_ground(size / 2.0, size, projection, &transform).unwrap();
+        let right = viewport_to_ground(
+            Vec2::new(size.x, size.y / 2.0),
+            size,
+            projection,
+            &transform,
+        )
+        .unwrap();
+        let top =
+            viewport_to_ground(Vec2::new(size.x / 2.0, 0.0), size, projection, &transform).unwrap();
+        // the camera sits at +x +z, so right is +x -z and up is away from the camera
+        assert!((right - center).dot(Vec3::new(1.0, 0.0, -1.0)) > 0.0);
+        assert!((top - center).dot(Vec3::new(-1.0, 0.0, -1.0)) > 0.0);
+    }
+
+    #[test]
+    fn camera_looking_along_the_ground_never_hits_it() {
+        let size = Vec2::new(800.0, 600.0);
+        let (projection, _) = camera(size.x, size.y);
+        let transform: GlobalTransform = Transform::from_xyz(0.0, 5.0, 5.0)
+            .looking_at(Vec3::new(0.0, 5.0, 0.0), Vec3::Y)
+            .into();
+        assert_eq!(
+            viewport_to_ground(size / 2.0, size, projection, &transform),
+            None
+        );
+    }
+}
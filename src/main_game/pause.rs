@@ -0,0 +1,100 @@
+use crate::audio::{volume_ui, VolumeSettings};
+use crate::main_game::input::{bindings_ui, capture_rebinding, Bindings, PlayerAction, Rebinding};
+use crate::{GameState, GameStateChange};
+use bevy::prelude::*;
+use bevy::window::WindowFocused;
+use bevy_egui::EguiContexts;
+use bevy_xpbd_3d::prelude::PhysicsLoop;
+use egui::CollapsingHeader;
+use leafwing_input_manager::prelude::*;
+
+pub struct PausePlugin;
+
+impl Plugin for PausePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Paused), freeze);
+        app.add_systems(OnExit(GameState::Paused), unfreeze);
+        app.add_systems(
+            Update,
+            (pause_on_action, pause_on_focus_lost)
+                .run_if(state_exists_and_equals(GameState::InGame)),
+        );
+        app.add_systems(
+            Update,
+            (pause_menu, resume_on_action.before(capture_rebinding))
+                .run_if(state_exists_and_equals(GameState::Paused)),
+        );
+    }
+}
+
+// Everything in a run is driven by virtual time, so stopping it also stops the tower timers,
+// the wave spawner and `TimeSinceGameStart`.
+fn freeze(mut time: ResMut<Time<Virtual>>, mut physics_loop: ResMut<PhysicsLoop>) {
+    time.pause();
+    physics_loop.pause();
+}
+
+fn unfreeze(mut time: ResMut<Time<Virtual>>, mut physics_loop: ResMut<PhysicsLoop>) {
+    time.unpause();
+    physics_loop.resume();
+}
+
+fn pause_on_action(
+    action_state: Res<ActionState<PlayerAction>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if action_state.just_pressed(PlayerAction::Pause) {
+        next_state.set(GameState::Paused);
+    }
+}
+
+fn pause_on_focus_lost(
+    mut focus_events: EventReader<WindowFocused>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if focus_events.read().any(|event| !event.focused) {
+        next_state.set(GameState::Paused);
+    }
+}
+
+fn resume_on_action(
+    action_state: Res<ActionState<PlayerAction>>,
+    rebinding: Res<Rebinding>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    // the press that finishes a rebind shouldn't also resume
+    if rebinding.0.is_none() && action_state.just_pressed(PlayerAction::Pause) {
+        next_state.set(GameState::InGame);
+    }
+}
+
+fn pause_menu(
+    mut contexts: EguiContexts,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut event_writer: EventWriter<GameStateChange>,
+    mut volume: ResMut<VolumeSettings>,
+    mut bindings: ResMut<Bindings>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    let ctx = contexts.ctx_mut();
+    egui::Window::new("paused")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(ctx, |ui| {
+            if ui.button("resume").clicked() {
+                rebinding.0 = None;
+                next_state.set(GameState::InGame);
+            }
+            CollapsingHeader::new("settings").show(ui, |ui| {
+                volume_ui(ui, &mut volume);
+                ui.separator();
+                bindings_ui(ui, &mut bindings, &mut rebinding);
+            });
+            // ends the run the same way dying does, so it still pays out
+            if ui.button("abandon run").clicked() {
+                rebinding.0 = None;
+                event_writer.send(GameStateChange::Staging);
+            }
+        });
+}
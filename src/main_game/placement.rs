@@ -0,0 +1,158 @@
+use crate::main_game::mouse::MousePos;
+use crate::main_game::selection::{CarriedTower, HoveredTower};
+use crate::main_game::tower::Tower;
+use crate::main_game::{calculate_available_towers, PlacedTowers, Score, TickSet};
+use crate::spatial::SpatialGrid;
+use crate::upgrade::Stats;
+use crate::GameState;
+use bevy::prelude::*;
+use std::f32::consts::TAU;
+
+// the ground plane spawned in `setup` is 20 by 20, centered on the origin
+const GROUND_HALF_SIZE: f32 = 10.0;
+// keeps the whole tower model on the ground
+const EDGE_MARGIN: f32 = 0.3;
+// minimum distance between two placed towers
+const TOWER_SPACING: f32 = 0.7;
+// how far from the cursor a blocked click looks for a free spot
+const SEARCH_RADIUS: f32 = 1.5;
+const SEARCH_STEP: f32 = 0.05;
+// towers stand this high above the ground
+const TOWER_HEIGHT: f32 = 0.3;
+
+const VALID_COLOR: Color = Color::rgba(0.2, 1.0, 0.2, 0.4);
+const INVALID_COLOR: Color = Color::rgba(1.0, 0.2, 0.2, 0.4);
+
+pub struct PlacementPlugin;
+
+impl Plugin for PlacementPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TowerGrid(SpatialGrid::new(TOWER_SPACING)));
+        app.add_systems(Startup, spawn_ghost);
+        app.add_systems(FixedUpdate, rebuild_tower_grid.in_set(TickSet::Input));
+        app.add_systems(Update, update_ghost);
+    }
+}
+
+// Tower positions as of the start of the tick.
+#[derive(Resource, Deref)]
+pub struct TowerGrid(SpatialGrid);
+
+// Shows where a click would put a tower, green if it can go there and red if it can't.
+#[derive(Component)]
+struct PlacementGhost;
+
+fn in_bounds(position: Vec3) -> bool {
+    let limit = GROUND_HALF_SIZE - EDGE_MARGIN;
+    position.x.abs() <= limit && position.z.abs() <= limit
+}
+
+fn is_free(towers: &SpatialGrid, position: Vec3) -> bool {
+    in_bounds(position)
+        && towers
+            .within(position, TOWER_SPACING)
+            .all(|(_, tower)| tower.xz().distance(position.xz()) > TOWER_SPACING)
+}
+
+// The free spot closest to `target`, searching outwards in rings up to `SEARCH_RADIUS`.
+pub fn find_placement(towers: &SpatialGrid, target: Vec3) -> Option<Vec3> {
+    let target = Vec3::new(target.x, TOWER_HEIGHT, target.z);
+    if is_free(towers, target) {
+        return Some(target);
+    }
+    let rings = (SEARCH_RADIUS / SEARCH_STEP).round() as u32;
+    (1..=rings).find_map(|ring| {
+        let radius = ring as f32 * SEARCH_STEP;
+        // roughly one sample per step along the ring
+        let samples = (TAU * radius / SEARCH_STEP).ceil() as u32;
+        (0..samples)
+            .map(|i| {
+                let angle = TAU * i as f32 / samples as f32;
+                target + Vec3::new(angle.cos(), 0.0, angle.sin()) * radius
+            })
+            .find(|candidate| is_free(towers, *candidate))
+    })
+}
+
+fn rebuild_tower_grid(
+    mut grid: ResMut<TowerGrid>,
+    towers: Query<(Entity, &Transform), With<Tower>>,
+) {
+    grid.0.clear();
+    for (tower, transform) in towers.iter() {
+        grid.0.insert(tower, transform.translation);
+    }
+}
+
+fn spawn_ghost(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(
+                shape::Cylinder {
+                    radius: TOWER_SPACING / 2.0,
+                    height: 0.02,
+                    ..default()
+                }
+                .into(),
+            ),
+            material: materials.add(StandardMaterial {
+                base_color: VALID_COLOR,
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            }),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        PlacementGhost,
+    ));
+}
+
+fn update_ghost(
+    state: Res<State<GameState>>,
+    mouse_pos: Res<MousePos>,
+    tower_grid: Res<TowerGrid>,
+    score: Res<Score>,
+    placed: Res<PlacedTowers>,
+    stats: Stats,
+    hovered: Res<HoveredTower>,
+    carried: Res<CarriedTower>,
+    mut ghosts: Query<
+        (&mut Transform, &mut Visibility, &Handle<StandardMaterial>),
+        With<PlacementGhost>,
+    >,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    // hovering a tower selects it instead of placing one
+    let visible = *state.get() == GameState::InGame && (carried.0.is_some() || hovered.0.is_none());
+    let Ok((mut transform, mut visibility, material)) = ghosts.get_single_mut() else {
+        return;
+    };
+    if !visible {
+        *visibility = Visibility::Hidden;
+        return;
+    }
+    *visibility = Visibility::Visible;
+    let has_slot = carried.0.is_some() || calculate_available_towers(*score, *placed, &stats) > 0;
+    let spot = find_placement(&tower_grid, mouse_pos.0);
+    let position = spot.unwrap_or(mouse_pos.0);
+    transform.translation = Vec3::new(position.x, 0.02, position.z);
+    let color = if has_slot && spot.is_some() {
+        VALID_COLOR
+    } else {
+        INVALID_COLOR
+    };
+    // only touch the asset when the color flips, every `get_mut` re-uploads it
+    if materials
+        .get(material)
+        .is_some_and(|material| material.base_color != color)
+    {
+        if let Some(material) = materials.get_mut(material) {
+            material.base_color = color;
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use crate::main_game::bullet::Bullet;
+use crate::main_game::enemy::{
+    Enemy, EnemyKind, EnemyTarget, LastHitBy, MaxHealth, Shield, Slowed, SpawnedAt,
+};
+use crate::main_game::wave::WaveMember;
+use crate::main_game::{Health, Speed, TickSet};
+use crate::GameStateChange;
+use bevy::ecs::system::EntityCommands;
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use bevy_xpbd_3d::prelude::*;
+
+pub struct PoolPlugin;
+
+impl Plugin for PoolPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EntityPool>();
+        app.add_systems(FixedUpdate, release_parked.in_set(TickSet::Input));
+        app.add_systems(Update, empty_on_game_start);
+    }
+}
+
+// Bullets and enemies that are done are parked here, hidden and without their gameplay
+// components, and handed out again instead of spawning new entities.
+#[derive(Resource, Default)]
+pub struct EntityPool {
+    bullets: Pool,
+    enemies: Pool,
+}
+
+#[derive(Default)]
+struct Pool {
+    free: Vec<Entity>,
+    // parked this tick, their removals may not have been applied yet
+    parking: Vec<Entity>,
+    parked: HashSet<Entity>,
+}
+
+impl Pool {
+    fn take(&mut self) -> Option<Entity> {
+        let entity = self.free.pop()?;
+        self.parked.remove(&entity);
+        Some(entity)
+    }
+
+    // false if the entity is already parked, several systems can be done with it in one tick
+    fn park(&mut self, entity: Entity) -> bool {
+        if !self.parked.insert(entity) {
+            return false;
+        }
+        self.parking.push(entity);
+        true
+    }
+
+    fn despawn_all(&mut self, commands: &mut Commands) {
+        for entity in self.parked.drain() {
+            commands.entity(entity).despawn();
+        }
+        self.free.clear();
+        self.parking.clear();
+    }
+}
+
+impl EntityPool {
+    pub fn spawn_bullet<'w, 's, 'a>(
+        &mut self,
+        commands: &'a mut Commands<'w, 's>,
+        bundle: impl Bundle,
+    ) -> EntityCommands<'w, 's, 'a> {
+        match self.bullets.take() {
+            Some(bullet) => {
+                let mut entity = commands.entity(bullet);
+                entity.insert(bundle);
+                entity
+            }
+            None => commands.spawn(bundle),
+        }
+    }
+
+    pub fn spawn_enemy<'w, 's, 'a>(
+        &mut self,
+        commands: &'a mut Commands<'w, 's>,
+        bundle: impl Bundle,
+    ) -> EntityCommands<'w, 's, 'a> {
+        match self.enemies.take() {
+            Some(enemy) => {
+                let mut entity = commands.entity(enemy);
+                entity.insert(bundle);
+                entity
+            }
+            None => commands.spawn(bundle),
+        }
+    }
+
+    pub fn recycle_bullet(&mut self, commands: &mut Commands, bullet: Entity) {
+        if self.bullets.park(bullet) {
+            commands
+                .entity(bullet)
+                .remove::<Bullet>()
+                .insert(parked_physics(RigidBody::Kinematic));
+        }
+    }
+
+    pub fn recycle_enemy(&mut self, commands: &mut Commands, enemy: Entity) {
+        if self.enemies.park(enemy) {
+            commands
+                .entity(enemy)
+                .remove::<(
+                    Enemy,
+                    Speed,
+                    Health,
+                    MaxHealth,
+                    EnemyKind,
+                    EnemyTarget,
+                    WaveMember,
+                    SpawnedAt,
+                    Shield,
+                    Slowed,
+                    LastHitBy,
+                    ExternalImpulse,
+                )>()
+                .insert(parked_physics(RigidBody::Static));
+        }
+    }
+}
+
+// Hidden, still and touching nothing until it is handed out again.
+fn parked_physics(rigid_body: RigidBody) -> impl Bundle {
+    (
+        Visibility::Hidden,
+        rigid_body,
+        CollisionLayers::NONE,
+        LinearVelocity::ZERO,
+        AngularVelocity::ZERO,
+    )
+}
+
+fn release_parked(mut entity_pool: ResMut<EntityPool>) {
+    let entity_pool = &mut *entity_pool;
+    for pool in [&mut entity_pool.bullets, &mut entity_pool.enemies] {
+        pool.free.append(&mut pool.parking);
+    }
+}
+
+// Every run starts with an empty pool, the entities left over from the last run would otherwise
+// change the order enemies and bullets are handed out in, and a replay would play out differently.
+fn empty_on_game_start(
+    mut event_reader: EventReader<GameStateChange>,
+    mut commands: Commands,
+    mut entity_pool: ResMut<EntityPool>,
+) {
+    for ev in event_reader.read() {
+        if let GameStateChange::MainGame = ev {
+            let entity_pool = &mut *entity_pool;
+            entity_pool.bullets.despawn_all(&mut commands);
+            entity_pool.enemies.despawn_all(&mut commands);
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use crate::difficulty::{Difficulty, Mutators};
+use crate::main_game::goal::ChooseOvertime;
+use crate::main_game::input::PlayerAction;
+use crate::main_game::mouse::MousePos;
+use crate::main_game::rng::{reseed_on_game_start, GameRng};
+use crate::main_game::selection::{HoveredTower, TowerCommand};
+use crate::main_game::tower::SelectedTowerType;
+use crate::main_game::{RunEnded, TickSet};
+use crate::upgrade::UpgradeLevels;
+use crate::{GameState, GameStateChange};
+use bevy::prelude::*;
+use leafwing_input_manager::plugin::InputManagerSystem;
+use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const REPLAY_VERSION: u32 = 1;
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TickInput>();
+        app.init_resource::<PendingInput>();
+        app.init_resource::<ReplayMode>();
+        app.init_resource::<Recorder>();
+        app.init_resource::<LastReplay>();
+        app.add_systems(
+            PreUpdate,
+            collect_live_input
+                .after(InputManagerSystem::Update)
+                .run_if(state_exists_and_equals(GameState::InGame).and_then(live_input)),
+        );
+        app.add_systems(Update, start_recording.after(reseed_on_game_start));
+        app.add_systems(FixedUpdate, next_tick_input.in_set(TickSet::Input));
+        app.add_systems(PostUpdate, finish_run);
+    }
+}
+
+// What the player did in one gameplay tick. Gameplay reads this instead of the live input, so
+// that a replay can stand in for the player.
+#[derive(Resource, Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct TickInput {
+    pub cursor: Vec3,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pressed: Vec<PlayerAction>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub just_pressed: Vec<PlayerAction>,
+    // a click over a tower selects it rather than placing one
+    #[serde(default)]
+    pub over_tower: bool,
+    pub tower_type: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<TowerCommand>,
+    // played on past the time goal instead of cashing out
+    #[serde(default)]
+    pub overtime: bool,
+}
+
+impl TickInput {
+    pub fn pressed(&self, action: PlayerAction) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: PlayerAction) -> bool {
+        self.just_pressed.contains(&action)
+    }
+}
+
+// Presses and commands since the last tick. A frame can fall between two ticks or hold several,
+// so each is handed to exactly one tick.
+#[derive(Resource, Default)]
+struct PendingInput {
+    just_pressed: Vec<PlayerAction>,
+    commands: Vec<TowerCommand>,
+    overtime: bool,
+}
+
+// Everything needed to play a run back: how it was set up and what the player did each tick.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Replay {
+    pub version: u32,
+    pub seed: u64,
+    pub difficulty: Difficulty,
+    pub mutators: Mutators,
+    pub upgrades: UpgradeLevels,
+    // how many ticks the run lasted
+    pub ticks: u32,
+    // each tick's input is only stored when it differs from the tick before
+    pub changes: Vec<(u32, TickInput)>,
+}
+
+impl Replay {
+    // Input recorded by another version would drive a different game, so it can't be played.
+    pub fn check_version(&self) -> Result<(), String> {
+        if self.version != REPLAY_VERSION {
+            return Err(format!(
+                "replay has version {}, only {} can be played",
+                self.version, REPLAY_VERSION
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Resource, Default)]
+pub enum ReplayMode {
+    // the player's own input, recorded as the run goes
+    #[default]
+    Live,
+    Playback(Playback),
+}
+
+pub struct Playback {
+    replay: Replay,
+    tick: u32,
+    next_change: usize,
+    input: TickInput,
+    finished: bool,
+}
+
+impl ReplayMode {
+    pub fn play(replay: Replay) -> Self {
+        ReplayMode::Playback(Playback {
+            replay,
+            tick: 0,
+            next_change: 0,
+            input: TickInput::default(),
+            finished: false,
+        })
+    }
+
+    pub fn is_playing(&self) -> bool {
+        matches!(self, ReplayMode::Playback(_))
+    }
+
+    // The replay being played back, whose setup the run uses in place of the player's.
+    pub fn replay(&self) -> Option<&Replay> {
+        match self {
+            ReplayMode::Live => None,
+            ReplayMode::Playback(playback) => Some(&playback.replay),
+        }
+    }
+}
+
+impl Playback {
+    fn next(&mut self) -> Option<&TickInput> {
+        if self.tick >= self.replay.ticks {
+            return None;
+        }
+        if let Some((_, input)) = self
+            .replay
+            .changes
+            .get(self.next_change)
+            .filter(|(tick, _)| *tick == self.tick)
+        {
+            self.input = input.clone();
+            self.next_change += 1;
+        }
+        self.tick += 1;
+        Some(&self.input)
+    }
+}
+
+// The run being recorded, none while a replay plays.
+#[derive(Resource, Default)]
+struct Recorder(Option<Replay>);
+
+impl Recorder {
+    fn record(&mut self, input: &TickInput) {
+        let Some(replay) = &mut self.0 else {
+            return;
+        };
+        if replay.changes.last().map(|(_, last)| last) != Some(input) {
+            replay.changes.push((replay.ticks, input.clone()));
+        }
+        replay.ticks += 1;
+    }
+}
+
+// The recording of the last run the player played themselves.
+#[derive(Resource, Default)]
+pub struct LastReplay(pub Option<Replay>);
+
+pub fn live_input(mode: Option<Res<ReplayMode>>) -> bool {
+    !mode.is_some_and(|mode| mode.is_playing())
+}
+
+// False once a replay has run out, so the run ends on the same tick the recording did.
+pub fn replay_has_input(mode: Res<ReplayMode>) -> bool {
+    match &*mode {
+        ReplayMode::Live => true,
+        ReplayMode::Playback(playback) => !playback.finished,
+    }
+}
+
+fn collect_live_input(
+    action_state: Res<ActionState<PlayerAction>>,
+    mut tower_commands: EventReader<TowerCommand>,
+    mut overtime_choices: EventReader<ChooseOvertime>,
+    mut pending: ResMut<PendingInput>,
+) {
+    for action in action_state.get_just_pressed() {
+        if !pending.just_pressed.contains(&action) {
+            pending.just_pressed.push(action);
+        }
+    }
+    pending.commands.extend(tower_commands.read().copied());
+    if overtime_choices.read().count() > 0 {
+        pending.overtime = true;
+    }
+}
+
+fn start_recording(
+    mut event_reader: EventReader<GameStateChange>,
+    mode: Res<ReplayMode>,
+    rng: Res<GameRng>,
+    difficulty: Res<Difficulty>,
+    mutators: Res<Mutators>,
+    levels: Res<UpgradeLevels>,
+    mut recorder: ResMut<Recorder>,
+    mut pending: ResMut<PendingInput>,
+) {
+    for ev in event_reader.read() {
+        if let GameStateChange::MainGame = ev {
+            *pending = PendingInput::default();
+            recorder.0 = (!mode.is_playing()).then(|| Replay {
+                version: REPLAY_VERSION,
+                seed: rng.seed(),
+                difficulty: *difficulty,
+                mutators: mutators.clone(),
+                upgrades: levels.clone(),
+                ticks: 0,
+                changes: Vec::new(),
+            });
+        }
+    }
+}
+
+fn next_tick_input(
+    mut tick_input: ResMut<TickInput>,
+    mut pending: ResMut<PendingInput>,
+    mut mode: ResMut<ReplayMode>,
+    mut recorder: ResMut<Recorder>,
+    action_state: Res<ActionState<PlayerAction>>,
+    hovered: Res<HoveredTower>,
+    mut mouse_pos: ResMut<MousePos>,
+    mut selected_type: ResMut<SelectedTowerType>,
+    mut event_writer: EventWriter<GameStateChange>,
+) {
+    let ReplayMode::Playback(playback) = &mut *mode else {
+        *tick_input = TickInput {
+            cursor: mouse_pos.0,
+            pressed: action_state.get_pressed(),
+            just_pressed: std::mem::take(&mut pending.just_pressed),
+            over_tower: hovered.0.is_some(),
+            tower_type: selected_type.0.clone(),
+            commands: std::mem::take(&mut pending.commands),
+            overtime: std::mem::take(&mut pending.overtime),
+        };
+        recorder.record(&tick_input);
+        return;
+    };
+    if playback.finished {
+        return;
+    }
+    match playback.next() {
+        Some(input) => {
+            *tick_input = input.clone();
+            mouse_pos.0 = input.cursor;
+            if selected_type.0 != input.tower_type {
+                selected_type.0 = input.tower_type.clone();
+            }
+        }
+        None => {
+            playback.finished = true;
+            event_writer.send(GameStateChange::Staging);
+        }
+    }
+}
+
+// Keeps the recording of the run that just ended, and hands control back after a replay.
+fn finish_run(
+    mut run_ended: EventReader<RunEnded>,
+    mut mode: ResMut<ReplayMode>,
+    mut recorder: ResMut<Recorder>,
+    mut last_replay: ResMut<LastReplay>,
+) {
+    if run_ended.read().next().is_none() {
+        return;
+    }
+    if let Some(replay) = recorder.0.take() {
+        last_replay.0 = Some(replay);
+    }
+    if mode.is_playing() {
+        *mode = ReplayMode::Live;
+    }
+}
@@ -0,0 +1,49 @@
+use crate::main_game::replay::ReplayMode;
+use crate::GameStateChange;
+use bevy::prelude::*;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+#[derive(Resource)]
+pub struct GameRng {
+    rng: ChaCha8Rng,
+    seed: u64,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            seed,
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn f32(&mut self) -> f32 {
+        self.rng.gen()
+    }
+}
+
+// When set every run uses this seed, otherwise each run gets a fresh one.
+#[derive(Resource, Default)]
+pub struct FixedSeed(pub Option<u64>);
+
+pub(crate) fn reseed_on_game_start(
+    mut event_reader: EventReader<GameStateChange>,
+    fixed_seed: Res<FixedSeed>,
+    replay_mode: Res<ReplayMode>,
+    mut rng: ResMut<GameRng>,
+) {
+    for ev in event_reader.read() {
+        if let GameStateChange::MainGame = ev {
+            let seed = match replay_mode.replay() {
+                Some(replay) => replay.seed,
+                None => fixed_seed.0.unwrap_or_else(rand::random),
+            };
+            *rng = GameRng::new(seed);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use crate::main_game::input::PlayerAction;
+use crate::main_game::replay::TickInput;
+use crate::main_game::tower::{
+    Destroyed, TargetingPolicy, Tower, TowerId, TowerKills, TowerKind, TowerLevel,
+};
+use crate::main_game::{PlacedTowers, RunGold, Score, TickSet};
+use crate::{GameState, GameStateChange};
+use bevy::prelude::*;
+use bevy_mod_picking::prelude::*;
+use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const SELL_GOLD_PER_LEVEL: f32 = 0.25;
+const MOVE_SCORE_COST_PER_LEVEL: u32 = 2;
+
+pub struct TowerSelectionPlugin;
+
+impl Plugin for TowerSelectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HoveredTower>();
+        app.init_resource::<SelectedTower>();
+        app.init_resource::<CarriedTower>();
+        app.add_event::<TowerHover>();
+        app.add_event::<TowerClicked>();
+        app.add_event::<TowerCommand>();
+        app.add_systems(Update, reset_on_game_start);
+        app.add_systems(
+            Update,
+            (track_hover_and_selection, issue_tower_commands)
+                .chain()
+                .run_if(state_exists_and_equals(GameState::InGame)),
+        );
+        app.add_systems(FixedUpdate, apply_tower_commands.in_set(TickSet::Gameplay));
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct HoveredTower(pub Option<Entity>);
+
+#[derive(Resource, Default)]
+pub struct SelectedTower(pub Option<Entity>);
+
+// A tower that was picked up, kept until it is put down again as the same tower.
+#[derive(Resource, Default)]
+pub struct CarriedTower(pub Option<PickedUpTower>);
+
+pub struct PickedUpTower {
+    pub id: TowerId,
+    pub kind: String,
+    pub level: u32,
+    pub kills: u32,
+    pub policy: TargetingPolicy,
+}
+
+// Sent by the picking listeners on the tower scene root, so hovering any child mesh counts.
+#[derive(Event)]
+pub enum TowerHover {
+    Over(Entity),
+    Out(Entity),
+}
+
+impl From<ListenerInput<Pointer<Over>>> for TowerHover {
+    fn from(event: ListenerInput<Pointer<Over>>) -> Self {
+        TowerHover::Over(event.listener())
+    }
+}
+
+impl From<ListenerInput<Pointer<Out>>> for TowerHover {
+    fn from(event: ListenerInput<Pointer<Out>>) -> Self {
+        TowerHover::Out(event.listener())
+    }
+}
+
+#[derive(Event)]
+pub struct TowerClicked {
+    tower: Entity,
+    button: PointerButton,
+}
+
+impl From<ListenerInput<Pointer<Click>>> for TowerClicked {
+    fn from(event: ListenerInput<Pointer<Click>>) -> Self {
+        TowerClicked {
+            tower: event.listener(),
+            button: event.button,
+        }
+    }
+}
+
+// Handed to the next tick through `TickInput`, so they are recorded along with the rest.
+#[derive(Event, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum TowerCommand {
+    Sell(TowerId),
+    PickUp(TowerId),
+    Target(TowerId, TargetingPolicy),
+}
+
+pub fn tower_listeners() -> impl Bundle {
+    (
+        On::<Pointer<Over>>::send_event::<TowerHover>(),
+        On::<Pointer<Out>>::send_event::<TowerHover>(),
+        On::<Pointer<Click>>::send_event::<TowerClicked>(),
+    )
+}
+
+pub fn sell_refund(level: u32) -> f32 {
+    SELL_GOLD_PER_LEVEL * level as f32
+}
+
+pub fn move_cost(level: u32) -> u32 {
+    MOVE_SCORE_COST_PER_LEVEL * level
+}
+
+fn reset_on_game_start(
+    mut event_reader: EventReader<GameStateChange>,
+    mut hovered: ResMut<HoveredTower>,
+    mut selected: ResMut<SelectedTower>,
+    mut carried: ResMut<CarriedTower>,
+) {
+    for ev in event_reader.read() {
+        if let GameStateChange::MainGame = ev {
+            hovered.0 = None;
+            selected.0 = None;
+            carried.0 = None;
+        }
+    }
+}
+
+fn track_hover_and_selection(
+    mut hover_events: EventReader<TowerHover>,
+    mut click_events: EventReader<TowerClicked>,
+    mut hovered: ResMut<HoveredTower>,
+    mut selected: ResMut<SelectedTower>,
+    towers: Query<(), (With<Tower>, Without<Destroyed>)>,
+) {
+    for event in hover_events.read() {
+        match *event {
+            TowerHover::Over(tower) => hovered.0 = Some(tower),
+            TowerHover::Out(tower) if hovered.0 == Some(tower) => hovered.0 = None,
+            TowerHover::Out(_) => {}
+        }
+    }
+    for click in click_events.read() {
+        if click.button != PointerButton::Primary {
+            continue;
+        }
+        selected.0 = if selected.0 == Some(click.tower) {
+            None
+        } else {
+            Some(click.tower)
+        };
+    }
+    // towers can be destroyed or sold out from under the pointer
+    if hovered.0.is_some_and(|tower| !towers.contains(tower)) {
+        hovered.0 = None;
+    }
+    if selected.0.is_some_and(|tower| !towers.contains(tower)) {
+        selected.0 = None;
+    }
+}
+
+fn issue_tower_commands(
+    action_state: Res<ActionState<PlayerAction>>,
+    hovered: Res<HoveredTower>,
+    selected: Res<SelectedTower>,
+    carried: Res<CarriedTower>,
+    towers: Query<&TowerId>,
+    mut tower_commands: EventWriter<TowerCommand>,
+) {
+    let Some(target) = hovered.0.or(selected.0) else {
+        return;
+    };
+    let Ok(&target) = towers.get(target) else {
+        return;
+    };
+    if action_state.just_pressed(PlayerAction::SellTower) {
+        tower_commands.send(TowerCommand::Sell(target));
+    } else if action_state.just_pressed(PlayerAction::MoveTower) && carried.0.is_none() {
+        tower_commands.send(TowerCommand::PickUp(target));
+    }
+}
+
+fn apply_tower_commands(
+    mut commands: Commands,
+    tick_input: Res<TickInput>,
+    mut towers: Query<
+        (
+            Entity,
+            &TowerId,
+            &TowerLevel,
+            &TowerKind,
+            &TowerKills,
+            &mut TargetingPolicy,
+        ),
+        (With<Tower>, Without<Destroyed>),
+    >,
+    mut placed: ResMut<PlacedTowers>,
+    mut score: ResMut<Score>,
+    mut run_gold: ResMut<RunGold>,
+    mut carried: ResMut<CarriedTower>,
+    mut selected: ResMut<SelectedTower>,
+) {
+    // despawning is deferred, so a tower sold or picked up this tick is still in the query
+    let mut removed: Vec<Entity> = Vec::new();
+    for command in tick_input.commands.iter() {
+        let (TowerCommand::Sell(id) | TowerCommand::PickUp(id) | TowerCommand::Target(id, _)) =
+            *command;
+        let Some((tower, _, level, kind, kills, mut policy)) =
+            towers.iter_mut().find(|(_, tower_id, ..)| **tower_id == id)
+        else {
+            continue;
+        };
+        if removed.contains(&tower) {
+            continue;
+        }
+        match *command {
+            TowerCommand::Sell(_) => {
+                placed.0 = placed.0.saturating_sub(1);
+                run_gold.refunds += sell_refund(level.0);
+            }
+            TowerCommand::PickUp(_) => {
+                let cost = move_cost(level.0);
+                if carried.0.is_some() || score.0 < cost {
+                    continue;
+                }
+                // the carried tower keeps its slot, so `placed` stays the same
+                score.0 -= cost;
+                carried.0 = Some(PickedUpTower {
+                    id,
+                    kind: kind.0.clone(),
+                    level: level.0,
+                    kills: kills.0,
+                    policy: *policy,
+                });
+            }
+            TowerCommand::Target(_, choice) => {
+                *policy = choice;
+                continue;
+            }
+        }
+        commands.entity(tower).despawn_recursive();
+        removed.push(tower);
+        if selected.0 == Some(tower) {
+            selected.0 = None;
+        }
+    }
+}
@@ -1,26 +1,48 @@
+use crate::main_game::game_over::TowerSummary;
+use crate::main_game::input::PlayerAction;
 use crate::main_game::mouse::MousePos;
-use crate::main_game::{calculate_available_towers, PlacedTowers, Score};
-use crate::{GameState, GameStateChange, UpgradeRadiusLvl};
-use bevy::input::mouse::MouseButtonInput;
+use crate::main_game::placement::{find_placement, TowerGrid};
+use crate::main_game::replay::TickInput;
+use crate::main_game::selection::{tower_listeners, CarriedTower, PickedUpTower};
+use crate::main_game::{calculate_available_towers, Health, PlacedTowers, Score, TickSet};
+use crate::ron_asset::RonAssetPlugin;
+use crate::upgrade::{Formula, Stat, Stats};
+use crate::GameStateChange;
 use bevy::prelude::*;
-use random_number::random;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::time::Duration;
 
 pub struct TowerPlugin;
 impl Plugin for TowerPlugin {
     fn build(&self, app: &mut App) {
+        app.add_plugins(RonAssetPlugin::<TowerTypes>::new(
+            "default.towers.ron",
+            &["towers.ron"],
+            include_str!("../../assets/default.towers.ron"),
+        ));
+        app.init_resource::<SelectedTowerType>();
+        app.init_resource::<NextTowerId>();
+        app.init_resource::<RunTowers>();
+        app.add_event::<SpawnTower>();
         app.add_systems(
-            Update,
-            (spawn_tower, set_tower_size)
+            FixedUpdate,
+            (place_tower, spawn_towers, set_tower_size)
                 .chain()
-                .run_if(state_exists_and_equals(GameState::InGame)),
+                .in_set(TickSet::Gameplay),
         );
         app.add_systems(
-            Update,
-            tower_progress_increase.run_if(state_exists_and_equals(GameState::InGame)),
+            FixedUpdate,
+            (
+                tower_progress_increase,
+                destroy_towers_without_health,
+                collapse_destroyed_towers,
+                set_tower_duration,
+            )
+                .in_set(TickSet::Gameplay),
         );
+        app.add_event::<TowerDestroyed>();
         app.add_systems(Update, clone_material);
-        app.add_systems(Update, set_tower_duration);
         app.add_systems(PostUpdate, on_game_end);
         app.insert_resource(TimeSinceGameStart(0.0));
     }
@@ -31,6 +53,8 @@ fn on_game_end(
     mut event_reader: EventReader<GameStateChange>,
     mut commands: Commands,
     mut time_since_game_start: ResMut<TimeSinceGameStart>,
+    mut next_tower_id: ResMut<NextTowerId>,
+    mut run_towers: ResMut<RunTowers>,
 ) {
     for e in event_reader.read() {
         match e {
@@ -42,6 +66,8 @@ fn on_game_end(
             }
             GameStateChange::MainGame => {
                 time_since_game_start.0 = 0.0;
+                next_tower_id.0 = 0;
+                run_towers.0.clear();
             }
         }
     }
@@ -51,40 +77,267 @@ fn on_game_end(
 #[derive(Component)]
 pub struct Tower(pub Timer);
 #[derive(Component)]
-pub struct TowerLevel(u32);
+pub struct TowerLevel(pub(crate) u32);
+#[derive(Component, Clone)]
+pub struct TowerKind(pub String);
+// enemies this tower landed the killing blow on
+#[derive(Component)]
+pub struct TowerKills(pub u32);
+// Numbers towers in the order they were placed, so a replay can name the same tower again.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct TowerId(pub u32);
+
+#[derive(Resource, Default)]
+struct NextTowerId(u32);
+
+// Every tower placed this run, kept up to date as it levels up and gets kills, and kept after
+// it is sold or destroyed.
+#[derive(Resource, Default)]
+pub struct RunTowers(pub BTreeMap<TowerId, TowerSummary>);
+
+#[derive(Asset, Resource, TypePath, Deserialize, Clone)]
+pub struct TowerTypes {
+    pub towers: Vec<TowerType>,
+}
+
+impl TowerTypes {
+    pub fn get(&self, id: &str) -> Option<&TowerType> {
+        self.towers.iter().find(|tower| tower.id == id)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TowerType {
+    pub id: String,
+    pub name: String,
+    // multiplies the model's colors, (1, 1, 1) leaves it as modelled
+    pub tint: (f32, f32, f32),
+    // shots per second at a given `TowerLevel`
+    pub fire_rate: Formula,
+    // multipliers on the attack radius and damage upgrades
+    pub range: f32,
+    pub damage: f32,
+    // enemies shot at per volley, plus `extra_targets_per_level` for every level past the first
+    pub max_targets: u32,
+    #[serde(default)]
+    pub extra_targets_per_level: f32,
+    #[serde(default)]
+    pub default_policy: TargetingPolicy,
+    pub projectile: Projectile,
+    // world units per second
+    #[serde(default = "default_projectile_speed")]
+    pub projectile_speed: f32,
+    pub projectile_color: (f32, f32, f32),
+}
+
+fn default_projectile_speed() -> f32 {
+    6.0
+}
+
+// Which enemies in range a tower shoots at first.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum TargetingPolicy {
+    #[default]
+    Nearest,
+    Farthest,
+    LowestHealth,
+    HighestHealth,
+    ClosestToCursor,
+    Oldest,
+}
+
+impl TargetingPolicy {
+    pub const ALL: [TargetingPolicy; 6] = [
+        TargetingPolicy::Nearest,
+        TargetingPolicy::Farthest,
+        TargetingPolicy::LowestHealth,
+        TargetingPolicy::HighestHealth,
+        TargetingPolicy::ClosestToCursor,
+        TargetingPolicy::Oldest,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            TargetingPolicy::Nearest => "nearest",
+            TargetingPolicy::Farthest => "farthest",
+            TargetingPolicy::LowestHealth => "lowest health",
+            TargetingPolicy::HighestHealth => "highest health",
+            TargetingPolicy::ClosestToCursor => "closest to cursor",
+            TargetingPolicy::Oldest => "oldest",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Projectile {
+    Homing,
+    // damages every enemy within `radius` of the one it hits
+    Splash {
+        radius: f32,
+    },
+    // multiplies the target's speed by `slow` for `secs`
+    Frost {
+        slow: f32,
+        secs: f32,
+    },
+    // jumps to the nearest enemy not hit yet, losing `falloff` of its damage each time
+    Chain {
+        jumps: u32,
+        range: f32,
+        falloff: f32,
+    },
+    // fires nothing, each shot earns `amount` gold per tower level
+    Gold {
+        amount: f32,
+    },
+    // flies straight at where the target was when fired, and can miss
+    Linear,
+    // flies straight through up to `enemies` enemies
+    Pierce {
+        enemies: u32,
+    },
+    // flies straight and adds `push` to the velocity of the enemy it hits, away from the tower
+    Knockback {
+        push: f32,
+    },
+}
+
+impl Projectile {
+    pub fn is_homing(self) -> bool {
+        !matches!(
+            self,
+            Projectile::Linear | Projectile::Pierce { .. } | Projectile::Knockback { .. }
+        )
+    }
+}
+
+impl TowerType {
+    pub fn max_targets(&self, level: u32) -> usize {
+        let extra = level.saturating_sub(1) as f32 * self.extra_targets_per_level;
+        (self.max_targets + extra as u32).max(1) as usize
+    }
+
+    pub fn tint(&self) -> Color {
+        Color::rgb(self.tint.0, self.tint.1, self.tint.2)
+    }
+
+    pub fn projectile_color(&self) -> Color {
+        Color::rgb(
+            self.projectile_color.0,
+            self.projectile_color.1,
+            self.projectile_color.2,
+        )
+    }
+}
+
+// The type the next placed tower will be, picked from the in-game panel.
+#[derive(Resource)]
+pub struct SelectedTowerType(pub String);
+
+impl Default for SelectedTowerType {
+    fn default() -> Self {
+        Self("basic".to_string())
+    }
+}
+
+#[derive(Event)]
+pub struct SpawnTower {
+    pub kind: String,
+    pub position: Vec3,
+    pub level: u32,
+    // set when a picked up tower is put back down
+    pub moved: Option<PickedUpTower>,
+}
 
 #[derive(Component)]
 struct TowerProgress(f32);
 
-fn set_tower_size(mut query: Query<(&mut Transform, &TowerLevel), With<Tower>>) {
+const TOWER_BASE_HEALTH: f32 = 5.0;
+const TOWER_HEALTH_PER_LEVEL: f32 = 1.0;
+// holding the upgrade action charges towers in range this much faster
+const UPGRADE_HOLD_RATE: f32 = 3.0;
+
+// A tower whose health ran out. It stops firing, sinks into the ground and is despawned.
+#[derive(Component)]
+pub struct Destroyed(Timer);
+
+#[derive(Event)]
+pub struct TowerDestroyed(pub Entity);
+
+fn destroy_towers_without_health(
+    mut commands: Commands,
+    towers: Query<(Entity, &Health), (With<Tower>, Without<Destroyed>)>,
+    mut placed: ResMut<PlacedTowers>,
+    mut destroyed_events: EventWriter<TowerDestroyed>,
+) {
+    for (tower, health) in towers.iter() {
+        if health.0 > 0.0 {
+            continue;
+        }
+        commands
+            .entity(tower)
+            .insert(Destroyed(Timer::from_seconds(1.0, TimerMode::Once)));
+        // losing a tower gives its slot back
+        placed.0 = placed.0.saturating_sub(1);
+        destroyed_events.send(TowerDestroyed(tower));
+    }
+}
+
+fn collapse_destroyed_towers(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut towers: Query<(Entity, &mut Transform, &mut Destroyed)>,
+) {
+    for (tower, mut transform, mut destroyed) in towers.iter_mut() {
+        destroyed.0.tick(time.delta());
+        transform.scale *= 1.0 - time.delta_seconds() * 2.0;
+        transform.translation.y -= time.delta_seconds() * 0.3;
+        if destroyed.0.finished() {
+            commands.entity(tower).despawn_recursive();
+        }
+    }
+}
+
+fn set_tower_size(
+    mut query: Query<(&mut Transform, &TowerLevel), (With<Tower>, Without<Destroyed>)>,
+) {
     for (mut transform, tower_level) in query.iter_mut() {
         transform.scale = Vec3::splat(((tower_level.0 as f32) / 125.0) + 0.1)
     }
 }
 
-fn set_tower_duration(mut query: Query<(&mut Tower, &TowerLevel), Changed<TowerLevel>>) {
-    for (mut tower, tower_level) in query.iter_mut() {
-        tower.0.set_duration(Duration::from_millis(
-            (1000.0 / ((tower_level.0 as f32).log(1.5) + 1.0)) as u64,
-        ))
+fn set_tower_duration(
+    tower_types: Res<TowerTypes>,
+    mut query: Query<(&mut Tower, &TowerLevel, &TowerKind), Changed<TowerLevel>>,
+) {
+    for (mut tower, tower_level, kind) in query.iter_mut() {
+        let Some(tower_type) = tower_types.get(&kind.0) else {
+            continue;
+        };
+        let shots_per_second = tower_type.fire_rate.eval(tower_level.0).max(0.01);
+        tower
+            .0
+            .set_duration(Duration::from_secs_f32(1.0 / shots_per_second));
     }
 }
 
 #[derive(Resource)]
 pub struct TimeSinceGameStart(pub(crate) f32);
 
-fn spawn_tower(
+fn place_tower(
     mouse_pos: Res<MousePos>,
-    mut mouse_event_reader: EventReader<MouseButtonInput>,
-    asset_server: Res<AssetServer>,
-    mut commands: Commands,
+    tick_input: Res<TickInput>,
     score: Res<Score>,
     mut placed: ResMut<PlacedTowers>,
     mut event_reader: EventReader<GameStateChange>,
-    towers: Query<&Transform, With<Tower>>,
+    tower_grid: Res<TowerGrid>,
     time: Res<Time>,
     mut last_elapsed: Local<f32>,
     mut time_since_game_start: ResMut<TimeSinceGameStart>,
+    stats: Stats,
+    mut carried: ResMut<CarriedTower>,
+    selected_type: Res<SelectedTowerType>,
+    mut spawn_events: EventWriter<SpawnTower>,
 ) {
     time_since_game_start.0 += time.delta_seconds();
     if !event_reader.is_empty()
@@ -92,80 +345,115 @@ fn spawn_tower(
         || *last_elapsed + 0.4 > time.elapsed_seconds()
     {
         event_reader.clear();
-        mouse_event_reader.clear();
         return;
     }
-    for event in mouse_event_reader.read() {
-        match event.button {
-            MouseButton::Left => {
-                if calculate_available_towers(*score, *placed) <= 0 {
-                    return;
-                }
-                placed.0 += 1;
-
-                *last_elapsed = time.elapsed_seconds();
-
-                let mut this_pos = Vec3::new(mouse_pos.0.x, 0.3, mouse_pos.0.z);
-                let mut intersects = true;
-                let move_x: f32 = random!();
-                while intersects {
-                    //println!("intersects");
-                    intersects = false;
-                    for tower in towers.iter() {
-                        if tower.translation.distance(this_pos) <= 0.7 {
-                            intersects = true;
-                        }
-                    }
-                    if intersects {
-                        if move_x <= 0.25 {
-                            this_pos.x += 0.01;
-                            this_pos.z += 0.01;
-                        } else if move_x <= 0.5 {
-                            this_pos.x += 0.01;
-                            this_pos.z -= 0.01;
-                        } else if move_x < 0.75 {
-                            this_pos.x -= 0.01;
-                            this_pos.z += 0.01;
-                        } else {
-                            this_pos.x -= 0.01;
-                            this_pos.z -= 0.01;
-                        }
-                    }
-                }
-
-                commands.spawn((
-                    SceneBundle {
-                        scene: asset_server.load("tower.glb#Scene0"),
-                        transform: Transform::default()
-                            .with_scale(Vec3::splat(0.1))
-                            .with_translation(this_pos),
-                        ..default()
-                    },
-                    Tower(Timer::new(
-                        Duration::from_millis(1000),
-                        TimerMode::Repeating,
-                    )),
-                    TowerLevel(1),
-                    TowerProgress(0.0),
-                ));
-                mouse_event_reader.clear();
-                return;
+    if !tick_input.just_pressed(PlayerAction::PlaceTower) {
+        return;
+    }
+    // clicking an existing tower selects it rather than placing one next to it
+    if carried.0.is_none() && tick_input.over_tower {
+        return;
+    }
+    // a carried tower already holds its slot
+    if carried.0.is_none() && calculate_available_towers(*score, *placed, &stats) == 0 {
+        return;
+    }
+    // a click with no free spot nearby is ignored rather than costing a tower
+    let Some(position) = find_placement(&tower_grid, mouse_pos.0) else {
+        return;
+    };
+    let spawn = match carried.0.take() {
+        Some(moved) => SpawnTower {
+            kind: moved.kind.clone(),
+            position,
+            level: moved.level,
+            moved: Some(moved),
+        },
+        None => {
+            placed.0 += 1;
+            SpawnTower {
+                kind: selected_type.0.clone(),
+                position,
+                level: stats.get(Stat::StartingTowerLevel).max(1.0) as u32,
+                moved: None,
             }
-            _ => {}
         }
+    };
+
+    *last_elapsed = time.elapsed_seconds();
+
+    spawn_events.send(spawn);
+}
+
+fn spawn_towers(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut spawn_events: EventReader<SpawnTower>,
+    tower_types: Res<TowerTypes>,
+    mut next_tower_id: ResMut<NextTowerId>,
+    mut run_towers: ResMut<RunTowers>,
+) {
+    for spawn in spawn_events.read() {
+        let tower_type = tower_types.get(&spawn.kind);
+        // a moved tower stays the same tower, with its kills and targeting
+        let (id, kills, policy) = match &spawn.moved {
+            Some(moved) => (moved.id, moved.kills, moved.policy),
+            None => {
+                let id = TowerId(next_tower_id.0);
+                next_tower_id.0 += 1;
+                let policy = tower_type.map_or(TargetingPolicy::default(), |t| t.default_policy);
+                (id, 0, policy)
+            }
+        };
+        run_towers.0.insert(
+            id,
+            TowerSummary {
+                name: tower_type.map_or(spawn.kind.clone(), |t| t.name.clone()),
+                level: spawn.level,
+                kills,
+            },
+        );
+        commands.spawn((
+            SceneBundle {
+                scene: asset_server.load("tower.glb#Scene0"),
+                transform: Transform::default()
+                    .with_scale(Vec3::splat(0.1))
+                    .with_translation(spawn.position),
+                ..default()
+            },
+            Tower(Timer::new(
+                Duration::from_millis(1000),
+                TimerMode::Repeating,
+            )),
+            TowerLevel(spawn.level),
+            TowerKind(spawn.kind.clone()),
+            policy,
+            TowerKills(kills),
+            id,
+            TowerProgress(0.0),
+            Health(TOWER_BASE_HEALTH + TOWER_HEALTH_PER_LEVEL * spawn.level as f32),
+            tower_listeners(),
+        ));
     }
 }
 
 fn clone_material(
     mut materials: ResMut<Assets<StandardMaterial>>,
-    tower_query: Query<Entity, With<Tower>>,
+    tower_types: Res<TowerTypes>,
+    tower_query: Query<(Entity, &TowerKind), With<Tower>>,
     children_query: Query<&Children>,
     mut query: Query<&mut Handle<StandardMaterial>, Added<Handle<StandardMaterial>>>,
 ) {
-    for tower in tower_query.iter() {
+    for (tower, kind) in tower_query.iter() {
+        let tint = tower_types
+            .get(&kind.0)
+            .map_or(Color::WHITE, TowerType::tint);
         for child in children_query.iter_descendants(tower) {
             if let Ok(mut mat) = query.get_mut(child) {
-                let material2 = materials.get(&*mat).unwrap().clone();
+                let mut material2 = materials.get(&*mat).unwrap().clone();
+                // green and blue are rewritten every frame by `tower_progress_increase`
+                let red = material2.base_color.r();
+                material2.base_color.set_r(red * tint.r());
                 *mat = materials.add(material2);
             }
         }
@@ -174,7 +462,6 @@ fn clone_material(
 
 fn tower_progress_increase(
     mouse_pos: Res<MousePos>,
-    mouse_button: Res<Input<MouseButton>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut tower_query: Query<
         (
@@ -182,26 +469,48 @@ fn tower_progress_increase(
             &Transform,
             &mut TowerProgress,
             &mut TowerLevel,
+            &mut Health,
             &Tower,
+            &TowerKind,
+            &TowerId,
         ),
-        With<Tower>,
+        Without<Destroyed>,
     >,
+    tower_types: Res<TowerTypes>,
+    mut run_towers: ResMut<RunTowers>,
     children_query: Query<&Children>,
     children_material_query: Query<&Handle<StandardMaterial>>,
     time: Res<Time>,
-    upgrade_radius_lvl: Res<UpgradeRadiusLvl>,
+    stats: Stats,
+    tick_input: Res<TickInput>,
 ) {
-    let upgrade_radius = (upgrade_radius_lvl.0 as f32).log(1.1) / 25.0 + 0.5;
+    let upgrade_radius = stats.get(Stat::UpgradeRadius);
+    let rate = if tick_input.pressed(PlayerAction::UpgradeTower) {
+        UPGRADE_HOLD_RATE
+    } else {
+        1.0
+    };
 
-    for (tower_entity, tower_pos, mut tower_progress, mut tower_level, tower) in
-        tower_query.iter_mut()
+    for (
+        tower_entity,
+        tower_pos,
+        mut tower_progress,
+        mut tower_level,
+        mut health,
+        tower,
+        kind,
+        id,
+    ) in tower_query.iter_mut()
     {
+        let tint = tower_types
+            .get(&kind.0)
+            .map_or(Color::WHITE, TowerType::tint);
         let mut t_pos = tower_pos.translation;
         t_pos.y = 0.0;
         let mut m_pos = mouse_pos.0;
         m_pos.y = 0.0;
         if t_pos.distance(m_pos) <= upgrade_radius {
-            tower_progress.0 += 1.0 / (20.0 * (tower_level.0 as f32 + 5.0));
+            tower_progress.0 += rate / (20.0 * (tower_level.0 as f32 + 5.0));
 
             for mat in children_query.iter_descendants(tower_entity) {
                 if let Ok(mat) = children_material_query.get(mat) {
@@ -209,8 +518,8 @@ fn tower_progress_increase(
                         .get_mut(mat)
                         .unwrap()
                         .base_color
-                        .set_b(0.5)
-                        .set_g(0.5);
+                        .set_b(0.5 * tint.b())
+                        .set_g(0.5 * tint.g());
                 }
             }
         } else {
@@ -220,8 +529,8 @@ fn tower_progress_increase(
                         .get_mut(mat)
                         .unwrap()
                         .base_color
-                        .set_b(0.3)
-                        .set_g(0.3);
+                        .set_b(0.3 * tint.b())
+                        .set_g(0.3 * tint.g());
                 }
             }
         }
@@ -244,6 +553,10 @@ fn tower_progress_increase(
         if tower_progress.0 >= 1.0 {
             tower_progress.0 = 0.0;
             tower_level.0 += 1;
+            health.0 += TOWER_HEALTH_PER_LEVEL;
+            if let Some(summary) = run_towers.0.get_mut(id) {
+                summary.level = tower_level.0;
+            }
         }
     }
 }
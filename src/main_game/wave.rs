@@ -0,0 +1,202 @@
+use crate::main_game::rng::GameRng;
+use crate::main_game::TickSet;
+use crate::ron_asset::RonAssetPlugin;
+use crate::upgrade::{Stat, Stats};
+use crate::GameStateChange;
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::f32::consts::TAU;
+
+pub struct WavePlugin;
+
+impl Plugin for WavePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(RonAssetPlugin::<WaveScript>::new(
+            "default.waves.ron",
+            &["waves.ron"],
+            include_str!("../../assets/default.waves.ron"),
+        ));
+        app.insert_resource(WaveDirector::default());
+        app.add_event::<WaveStarted>();
+        app.add_event::<WaveCleared>();
+        app.add_event::<SpawnEnemy>();
+        app.add_systems(Update, reset_on_game_start);
+        app.add_systems(FixedUpdate, direct_waves.in_set(TickSet::Gameplay));
+    }
+}
+
+#[derive(Asset, Resource, TypePath, Deserialize, Clone)]
+pub struct WaveScript {
+    pub initial_delay: f32,
+    pub waves: Vec<WaveDef>,
+    pub endless: EndlessScaling,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct WaveDef {
+    pub groups: Vec<SpawnGroup>,
+    pub spawn_interval: f32,
+    pub spawn_radius: f32,
+    pub delay_after: f32,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SpawnGroup {
+    pub enemy: String,
+    pub count: u32,
+}
+
+// Once the script runs out the last wave repeats, growing by these factors each time.
+#[derive(Deserialize, Clone)]
+pub struct EndlessScaling {
+    pub count_growth: f32,
+    pub interval_decay: f32,
+    pub min_interval: f32,
+}
+
+impl WaveScript {
+    fn wave(&self, number: u32) -> WaveDef {
+        let index = number.saturating_sub(1) as usize;
+        if let Some(wave) = self.waves.get(index) {
+            return wave.clone();
+        }
+        let mut wave = self.waves.last().cloned().unwrap_or(WaveDef {
+            groups: Vec::new(),
+            spawn_interval: 1.0,
+            spawn_radius: 7.5,
+            delay_after: 10.0,
+        });
+        let extra = (index + 1 - self.waves.len()) as i32;
+        for group in wave.groups.iter_mut() {
+            group.count =
+                (group.count as f32 * self.endless.count_growth.powi(extra)).ceil() as u32;
+        }
+        wave.spawn_interval = (wave.spawn_interval * self.endless.interval_decay.powi(extra))
+            .max(self.endless.min_interval);
+        wave
+    }
+}
+
+#[derive(Event)]
+pub struct WaveStarted(pub u32);
+
+#[derive(Event)]
+pub struct WaveCleared(pub u32);
+
+#[derive(Event)]
+pub struct SpawnEnemy {
+    pub kind: String,
+    pub position: Vec2,
+    pub wave: u32,
+    // override the archetype's stats, used for duplicated enemies
+    pub speed: Option<f32>,
+    pub health: Option<f32>,
+    pub max_health: Option<f32>,
+    pub shielded: Option<bool>,
+}
+
+#[derive(Component)]
+pub struct WaveMember(pub u32);
+
+#[derive(Resource, Default)]
+pub struct WaveDirector {
+    pub wave: u32,
+    next_wave_in: Option<f32>,
+    spawning: Option<ActiveWave>,
+    uncleared: Vec<u32>,
+}
+
+struct ActiveWave {
+    def: WaveDef,
+    queue: Vec<String>,
+    spawn_timer: f32,
+}
+
+fn reset_on_game_start(
+    mut event_reader: EventReader<GameStateChange>,
+    mut director: ResMut<WaveDirector>,
+) {
+    for ev in event_reader.read() {
+        if let GameStateChange::MainGame = ev {
+            *director = WaveDirector::default();
+        }
+    }
+}
+
+pub(crate) fn direct_waves(
+    time: Res<Time>,
+    script: Res<WaveScript>,
+    mut director: ResMut<WaveDirector>,
+    mut rng: ResMut<GameRng>,
+    members: Query<&WaveMember>,
+    mut spawn_events: EventWriter<SpawnEnemy>,
+    mut started_events: EventWriter<WaveStarted>,
+    mut cleared_events: EventWriter<WaveCleared>,
+    stats: Stats,
+) {
+    let dt = time.delta_seconds();
+    let spawn_rate = stats.modifiers().spawn_rate;
+    let director = &mut *director;
+
+    // enemies requested last tick have been spawned by now, so an empty wave really is cleared
+    let spawning_wave = director.spawning.as_ref().map(|_| director.wave);
+    director.uncleared.retain(|&wave| {
+        if Some(wave) == spawning_wave || members.iter().any(|member| member.0 == wave) {
+            return true;
+        }
+        cleared_events.send(WaveCleared(wave));
+        false
+    });
+
+    let next_wave_in = director
+        .next_wave_in
+        .get_or_insert_with(|| script.initial_delay + stats.get(Stat::SpawnDelay).max(0.0));
+    *next_wave_in -= dt;
+    if *next_wave_in <= 0.0 && director.spawning.is_none() {
+        director.wave += 1;
+        let def = script.wave(director.wave);
+        // interleave the groups so mixed waves don't arrive one type at a time
+        let mut remaining: Vec<_> = def.groups.iter().map(|g| (&g.enemy, g.count)).collect();
+        let mut queue = Vec::new();
+        while remaining.iter().any(|(_, count)| *count > 0) {
+            for (enemy, count) in remaining.iter_mut() {
+                if *count > 0 {
+                    *count -= 1;
+                    queue.push((*enemy).clone());
+                }
+            }
+        }
+        queue.reverse();
+        director.spawning = Some(ActiveWave {
+            def,
+            queue,
+            spawn_timer: 0.0,
+        });
+        director.uncleared.push(director.wave);
+        started_events.send(WaveStarted(director.wave));
+    }
+
+    if let Some(active) = director.spawning.as_mut() {
+        active.spawn_timer -= dt;
+        while active.spawn_timer <= 0.0 {
+            let Some(kind) = active.queue.pop() else {
+                break;
+            };
+            active.spawn_timer += active.def.spawn_interval / spawn_rate;
+            let angle = rng.f32() * TAU;
+            spawn_events.send(SpawnEnemy {
+                kind,
+                position: Vec2::new(angle.cos(), angle.sin()) * active.def.spawn_radius,
+                wave: director.wave,
+                speed: None,
+                health: None,
+                max_health: None,
+                shielded: None,
+            });
+        }
+        if active.queue.is_empty() {
+            director.next_wave_in = Some(active.def.delay_after);
+            director.spawning = None;
+        }
+    }
+}
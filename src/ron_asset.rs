@@ -0,0 +1,106 @@
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext};
+use bevy::prelude::*;
+use bevy::utils::BoxedFuture;
+use serde::de::DeserializeOwned;
+use std::marker::PhantomData;
+
+// Loads a RON asset into a resource of the same type. The embedded copy is parsed at build time so
+// the resource is always present, and is replaced by the file on disk once it loads or is modified.
+pub struct RonAssetPlugin<A> {
+    path: &'static str,
+    extensions: &'static [&'static str],
+    embedded: &'static str,
+    marker: PhantomData<fn() -> A>,
+}
+
+impl<A> RonAssetPlugin<A> {
+    pub fn new(
+        path: &'static str,
+        extensions: &'static [&'static str],
+        embedded: &'static str,
+    ) -> Self {
+        Self {
+            path,
+            extensions,
+            embedded,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<A: Asset + Resource + Clone + DeserializeOwned> Plugin for RonAssetPlugin<A> {
+    fn build(&self, app: &mut App) {
+        let embedded: A = ron::from_str(self.embedded)
+            .unwrap_or_else(|err| panic!("embedded {} is invalid: {err}", self.path));
+        app.insert_resource(embedded);
+        app.init_asset::<A>();
+        app.register_asset_loader(RonAssetLoader::<A> {
+            extensions: self.extensions,
+            marker: PhantomData,
+        });
+        let path = self.path;
+        app.add_systems(
+            Startup,
+            move |mut commands: Commands, asset_server: Res<AssetServer>| {
+                commands.insert_resource(RonAssetHandle::<A>(asset_server.load(path)));
+            },
+        );
+        app.add_systems(PreUpdate, apply_ron_asset::<A>);
+    }
+}
+
+#[derive(Resource)]
+struct RonAssetHandle<A: Asset>(Handle<A>);
+
+fn apply_ron_asset<A: Asset + Resource + Clone>(
+    mut events: EventReader<AssetEvent<A>>,
+    handle: Option<Res<RonAssetHandle<A>>>,
+    assets: Res<Assets<A>>,
+    mut resource: ResMut<A>,
+) {
+    let Some(handle) = handle else {
+        events.clear();
+        return;
+    };
+    for event in events.read() {
+        match event {
+            AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }
+                if *id == handle.0.id() =>
+            {
+                if let Some(asset) = assets.get(*id) {
+                    *resource = asset.clone();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+struct RonAssetLoader<A> {
+    extensions: &'static [&'static str],
+    marker: PhantomData<fn() -> A>,
+}
+
+impl<A: Asset + DeserializeOwned> AssetLoader for RonAssetLoader<A> {
+    type Asset = A;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<A, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Ok(ron::de::from_bytes(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        self.extensions
+    }
+}
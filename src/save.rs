@@ -0,0 +1,271 @@
+use crate::audio::VolumeSettings;
+use crate::main_game::input::Bindings;
+use crate::main_game::replay::{LastReplay, Replay};
+use crate::upgrade::UpgradeLevels;
+use crate::{Diamonds, GameState, Gold, LifetimeStats};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const SAVE_VERSION: u32 = 2;
+const SAVE_KEY: &str = "save";
+const BINDINGS_KEY: &str = "bindings";
+const SETTINGS_KEY: &str = "settings";
+const REPLAY_KEY: &str = "replay";
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Startup,
+            (load_game, load_bindings, load_settings, load_replay),
+        );
+        app.add_systems(
+            Last,
+            (
+                // the save follows upgrade purchases and finished runs, never a run in progress
+                save_game_on_change.run_if(not(state_exists_and_equals(GameState::InGame))),
+                save_bindings_on_change,
+                save_settings_on_change,
+                save_replay_on_change,
+            ),
+        );
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SaveData {
+    pub version: u32,
+    pub gold: f32,
+    #[serde(default)]
+    pub diamonds: u32,
+    #[serde(default)]
+    pub upgrades: BTreeMap<String, u32>,
+    #[serde(default)]
+    pub stats: LifetimeStats,
+    // version 1 stored each upgrade level in its own field
+    #[serde(default, skip_serializing)]
+    upgrade_radius_lvl: Option<u32>,
+    #[serde(default, skip_serializing)]
+    attack_radius_lvl: Option<u32>,
+    #[serde(default, skip_serializing)]
+    damage_lvl: Option<u32>,
+    #[serde(default, skip_serializing)]
+    gold_conversion_rate_lvl: Option<u32>,
+}
+
+impl SaveData {
+    fn migrate(mut self) -> Self {
+        if self.version < 2 {
+            for (id, level) in [
+                ("upgrade_radius", self.upgrade_radius_lvl.take()),
+                ("attack_radius", self.attack_radius_lvl.take()),
+                ("damage", self.damage_lvl.take()),
+                ("gold_conversion_rate", self.gold_conversion_rate_lvl.take()),
+            ] {
+                if let Some(level) = level {
+                    self.upgrades.insert(id.to_string(), level);
+                }
+            }
+        }
+        self.version = SAVE_VERSION;
+        self
+    }
+}
+
+fn load_game(
+    mut gold: ResMut<Gold>,
+    mut diamonds: ResMut<Diamonds>,
+    mut levels: ResMut<UpgradeLevels>,
+    mut stats: ResMut<LifetimeStats>,
+) {
+    let Some(contents) = storage::read(SAVE_KEY) else {
+        return;
+    };
+    let data = match ron::from_str::<SaveData>(&contents) {
+        Ok(data) => data,
+        Err(err) => {
+            warn!("ignoring unreadable save: {err}");
+            return;
+        }
+    };
+    if data.version > SAVE_VERSION {
+        warn!(
+            "ignoring save with version {}, newest supported is {}",
+            data.version, SAVE_VERSION
+        );
+        return;
+    }
+    let data = data.migrate();
+    gold.0 = data.gold;
+    diamonds.0 = data.diamonds;
+    levels.0 = data.upgrades;
+    *stats = data.stats;
+}
+
+fn save_game_on_change(
+    gold: Res<Gold>,
+    diamonds: Res<Diamonds>,
+    levels: Res<UpgradeLevels>,
+    stats: Res<LifetimeStats>,
+) {
+    if !(gold.is_changed() || diamonds.is_changed() || levels.is_changed() || stats.is_changed()) {
+        return;
+    }
+    let data = SaveData {
+        version: SAVE_VERSION,
+        gold: gold.0,
+        diamonds: diamonds.0,
+        upgrades: levels.0.clone(),
+        stats: stats.clone(),
+        upgrade_radius_lvl: None,
+        attack_radius_lvl: None,
+        damage_lvl: None,
+        gold_conversion_rate_lvl: None,
+    };
+    match ron::ser::to_string_pretty(&data, ron::ser::PrettyConfig::default()) {
+        Ok(contents) => {
+            if let Err(err) = storage::write(SAVE_KEY, &contents) {
+                warn!("failed to write save: {err}");
+            }
+        }
+        Err(err) => warn!("failed to serialize save: {err}"),
+    }
+}
+
+// Bindings live in their own file so a bad save never resets the controls, or the reverse.
+fn load_bindings(mut bindings: ResMut<Bindings>) {
+    let Some(contents) = storage::read(BINDINGS_KEY) else {
+        return;
+    };
+    match ron::from_str::<Bindings>(&contents) {
+        Ok(mut loaded) => {
+            // actions added since the file was written keep their defaults
+            for (action, defaults) in Bindings::default().0 {
+                loaded.0.entry(action).or_insert(defaults);
+            }
+            *bindings = loaded;
+        }
+        Err(err) => warn!("ignoring unreadable bindings: {err}"),
+    }
+}
+
+fn save_bindings_on_change(bindings: Res<Bindings>) {
+    if !bindings.is_changed() {
+        return;
+    }
+    match ron::ser::to_string_pretty(&*bindings, ron::ser::PrettyConfig::default()) {
+        Ok(contents) => {
+            if let Err(err) = storage::write(BINDINGS_KEY, &contents) {
+                warn!("failed to write bindings: {err}");
+            }
+        }
+        Err(err) => warn!("failed to serialize bindings: {err}"),
+    }
+}
+
+fn load_settings(mut volume: ResMut<VolumeSettings>) {
+    let Some(contents) = storage::read(SETTINGS_KEY) else {
+        return;
+    };
+    match ron::from_str::<VolumeSettings>(&contents) {
+        Ok(loaded) => *volume = loaded,
+        Err(err) => warn!("ignoring unreadable settings: {err}"),
+    }
+}
+
+fn save_settings_on_change(volume: Res<VolumeSettings>) {
+    if !volume.is_changed() {
+        return;
+    }
+    match ron::ser::to_string_pretty(&*volume, ron::ser::PrettyConfig::default()) {
+        Ok(contents) => {
+            if let Err(err) = storage::write(SETTINGS_KEY, &contents) {
+                warn!("failed to write settings: {err}");
+            }
+        }
+        Err(err) => warn!("failed to serialize settings: {err}"),
+    }
+}
+
+// Only the last run is kept, so "replay last run" still works after a restart.
+fn load_replay(mut last_replay: ResMut<LastReplay>) {
+    let Some(contents) = storage::read(REPLAY_KEY) else {
+        return;
+    };
+    let replay = match ron::from_str::<Replay>(&contents) {
+        Ok(replay) => replay,
+        Err(err) => {
+            warn!("ignoring unreadable replay: {err}");
+            return;
+        }
+    };
+    if let Err(err) = replay.check_version() {
+        warn!("ignoring {err}");
+        return;
+    }
+    last_replay.0 = Some(replay);
+}
+
+fn save_replay_on_change(last_replay: Res<LastReplay>) {
+    if !last_replay.is_changed() {
+        return;
+    }
+    let Some(replay) = &last_replay.0 else {
+        return;
+    };
+    match ron::to_string(replay) {
+        Ok(contents) => {
+            if let Err(err) = storage::write(REPLAY_KEY, &contents) {
+                warn!("failed to write replay: {err}");
+            }
+        }
+        Err(err) => warn!("failed to serialize replay: {err}"),
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub mod storage {
+    use std::path::PathBuf;
+
+    fn path(key: &str) -> Option<PathBuf> {
+        Some(
+            dirs::data_dir()?
+                .join("one_tower")
+                .join(format!("{key}.ron")),
+        )
+    }
+
+    pub fn read(key: &str) -> Option<String> {
+        std::fs::read_to_string(path(key)?).ok()
+    }
+
+    pub fn write(key: &str, contents: &str) -> Result<(), String> {
+        let path = path(key).ok_or("no user data directory")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(path, contents).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(target_family = "wasm")]
+pub mod storage {
+    fn local_storage() -> Option<web_sys::Storage> {
+        web_sys::window()?.local_storage().ok()?
+    }
+
+    pub fn read(key: &str) -> Option<String> {
+        local_storage()?
+            .get_item(&format!("one_tower.{key}"))
+            .ok()?
+    }
+
+    pub fn write(key: &str, contents: &str) -> Result<(), String> {
+        local_storage()
+            .ok_or("localStorage is unavailable")?
+            .set_item(&format!("one_tower.{key}"), contents)
+            .map_err(|e| format!("{e:?}"))
+    }
+}
@@ -0,0 +1,48 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+// A uniform grid over the ground plane for "what is within this radius" queries. Entities are
+// bucketed by their (x, z) cell, so a query only looks at the handful of cells its circle touches
+// instead of every entity.
+pub struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<IVec2, Vec<(Entity, Vec3)>>,
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::default(),
+        }
+    }
+
+    fn cell(&self, position: Vec3) -> IVec2 {
+        (position.xz() / self.cell_size).floor().as_ivec2()
+    }
+
+    // Keeps the allocated cells around, it is refilled every frame.
+    pub fn clear(&mut self) {
+        for entities in self.cells.values_mut() {
+            entities.clear();
+        }
+    }
+
+    pub fn insert(&mut self, entity: Entity, position: Vec3) {
+        let cell = self.cell(position);
+        self.cells.entry(cell).or_default().push((entity, position));
+    }
+
+    // Every entity within `radius` of `center` on the ground plane, cell by cell in a fixed order
+    // so results don't depend on hashing.
+    pub fn within(&self, center: Vec3, radius: f32) -> impl Iterator<Item = (Entity, Vec3)> + '_ {
+        let min = self.cell(center - Vec3::splat(radius));
+        let max = self.cell(center + Vec3::splat(radius));
+        (min.x..=max.x)
+            .flat_map(move |x| (min.y..=max.y).map(move |z| IVec2::new(x, z)))
+            .filter_map(move |cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+            .filter(move |(_, position)| position.xz().distance(center.xz()) <= radius)
+    }
+}
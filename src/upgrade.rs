@@ -0,0 +1,197 @@
+use crate::difficulty::{Difficulty, DifficultyPresets, Mutator, Mutators, RunModifiers};
+use crate::main_game::replay::ReplayMode;
+use crate::ron_asset::RonAssetPlugin;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub struct UpgradePlugin;
+
+impl Plugin for UpgradePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(RonAssetPlugin::<UpgradeRegistry>::new(
+            "default.upgrades.ron",
+            &["upgrades.ron"],
+            include_str!("../assets/default.upgrades.ron"),
+        ));
+        app.insert_resource(UpgradeLevels::default());
+    }
+}
+
+#[derive(Asset, Resource, TypePath, Deserialize, Clone)]
+pub struct UpgradeRegistry {
+    pub upgrades: Vec<UpgradeDef>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct UpgradeDef {
+    pub id: String,
+    pub name: String,
+    pub stat: Stat,
+    #[serde(default)]
+    pub currency: Currency,
+    #[serde(default = "default_start_level")]
+    pub start_level: u32,
+    #[serde(default)]
+    pub max_level: Option<u32>,
+    pub cost: CostCurve,
+    pub effect: Formula,
+}
+
+fn default_start_level() -> u32 {
+    1
+}
+
+// Gold upgrades are bought between runs, diamond upgrades form the permanent tree.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Currency {
+    #[default]
+    Gold,
+    Diamonds,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Stat {
+    AttackRadius,
+    Damage,
+    GoldConversionRate,
+    UpgradeRadius,
+    MaxHealth,
+    StartingTowers,
+    StartingTowerLevel,
+    SpawnDelay,
+}
+
+impl Stat {
+    pub fn label(self) -> &'static str {
+        match self {
+            Stat::AttackRadius => "attack radius",
+            Stat::Damage => "bullet damage",
+            Stat::GoldConversionRate => "gold conversion rate",
+            Stat::UpgradeRadius => "upgrade radius",
+            Stat::MaxHealth => "max health",
+            Stat::StartingTowers => "extra starting towers",
+            Stat::StartingTowerLevel => "starting tower level",
+            Stat::SpawnDelay => "extra spawn delay",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub enum CostCurve {
+    LevelPlusLog2 { log_multiplier: u32 },
+    Linear { base: u32, per_level: u32 },
+    Exponential { base: f32, growth: f32 },
+}
+
+impl CostCurve {
+    pub fn cost(&self, level: u32) -> u32 {
+        match *self {
+            CostCurve::LevelPlusLog2 { log_multiplier } => {
+                level + (log_multiplier * level.max(1).ilog2())
+            }
+            CostCurve::Linear { base, per_level } => base + per_level * level,
+            CostCurve::Exponential { base, growth } => (base * growth.powi(level as i32)) as u32,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub enum Formula {
+    Linear {
+        divisor: f32,
+        offset: f32,
+    },
+    Log {
+        base: f32,
+        divisor: f32,
+        offset: f32,
+    },
+}
+
+impl Formula {
+    pub fn eval(&self, level: u32) -> f32 {
+        match *self {
+            Formula::Linear { divisor, offset } => level as f32 / divisor + offset,
+            Formula::Log {
+                base,
+                divisor,
+                offset,
+            } => (level as f32).log(base) / divisor + offset,
+        }
+    }
+}
+
+#[derive(Resource, Serialize, Deserialize, Default, Clone, Debug)]
+pub struct UpgradeLevels(pub BTreeMap<String, u32>);
+
+impl UpgradeLevels {
+    pub fn level(&self, upgrade: &UpgradeDef) -> u32 {
+        self.0
+            .get(&upgrade.id)
+            .copied()
+            .unwrap_or(upgrade.start_level)
+    }
+}
+
+impl UpgradeRegistry {
+    pub fn stat(&self, stat: Stat, levels: &UpgradeLevels) -> f32 {
+        self.upgrades
+            .iter()
+            .filter(|upgrade| upgrade.stat == stat)
+            .map(|upgrade| upgrade.effect.eval(levels.level(upgrade)))
+            .sum()
+    }
+
+    pub fn stats(&self, currency: Currency) -> Vec<Stat> {
+        let mut stats = Vec::new();
+        for upgrade in self.upgrades.iter().filter(|u| u.currency == currency) {
+            if !stats.contains(&upgrade.stat) {
+                stats.push(upgrade.stat);
+            }
+        }
+        stats
+    }
+}
+
+#[derive(SystemParam)]
+pub struct Stats<'w> {
+    registry: Res<'w, UpgradeRegistry>,
+    levels: Res<'w, UpgradeLevels>,
+    difficulty: Res<'w, Difficulty>,
+    mutators: Res<'w, Mutators>,
+    presets: Res<'w, DifficultyPresets>,
+    replay_mode: Res<'w, ReplayMode>,
+}
+
+impl Stats<'_> {
+    // a replay plays out with the setup it was recorded with
+    fn setup(&self) -> (&UpgradeLevels, Difficulty, &Mutators) {
+        match self.replay_mode.replay() {
+            Some(replay) => (&replay.upgrades, replay.difficulty, &replay.mutators),
+            None => (&self.levels, *self.difficulty, &self.mutators),
+        }
+    }
+
+    pub fn get(&self, stat: Stat) -> f32 {
+        let (levels, _, mutators) = self.setup();
+        if stat == Stat::MaxHealth && mutators.has(Mutator::FragileCursor) {
+            return 1.0;
+        }
+        if mutators.has(Mutator::NoUpgrades) {
+            // every upgrade at its starting level
+            return self.registry.stat(stat, &UpgradeLevels::default());
+        }
+        self.registry.stat(stat, levels)
+    }
+
+    pub fn difficulty(&self) -> Difficulty {
+        self.setup().1
+    }
+
+    pub fn modifiers(&self) -> RunModifiers {
+        let (_, difficulty, mutators) = self.setup();
+        self.presets.modifiers(difficulty, mutators)
+    }
+}